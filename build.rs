@@ -12,8 +12,35 @@ fn main() {
         Ok(output) if output.status.success() => {
             from_utf8(&output.stdout).unwrap().trim().to_string()
         }
-        _ => format!("no version info on build"),
+        _ => "no version info on build".to_string(),
     };
 
     println!("cargo:rustc-env=EPIPHYTE_BUILD_VERSION={}", version);
+
+    // once any path is printed, cargo reruns this only when one of them changes, code
+    // generation below prints the proto, so the version needs its own inputs
+    for path in [
+        ".git/HEAD",
+        ".git/refs",
+        ".git/index",
+        "src",
+        "proto",
+        "build.rs",
+    ] {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+
+    #[cfg(feature = "grpc")]
+    compile_protos();
+}
+
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    let mut config = tonic_prost_build::Config::new();
+    config.protoc_executable(protoc_bin_vendored::protoc_bin_path().unwrap());
+
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_with_config(config, &["proto/epiphyte.proto"], &["proto"])
+        .unwrap();
 }
@@ -0,0 +1,161 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::Arc,
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::Identifier;
+
+pub const TOKEN_ENV: &str = "EPIPHYTE_TOKEN";
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Auth {
+    #[serde(default)]
+    pub generate: bool,
+    #[serde(default)]
+    pub tokens: Vec<Token>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Token {
+    pub token: String,
+    pub paths: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Scope {
+    All,
+    Paths(HashSet<String>),
+}
+
+impl Scope {
+    pub fn allows(&self, path: &str) -> bool {
+        match self {
+            Scope::All => true,
+            Scope::Paths(paths) => paths.contains(path),
+        }
+    }
+}
+
+/// Accepted bearer tokens and their scopes. Empty means authentication is disabled.
+#[derive(Clone, Debug, Default)]
+pub struct Tokens(HashMap<String, Scope>);
+
+impl Tokens {
+    pub fn resolve(auth: &Auth, paths: &[Identifier]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut tokens = HashMap::new();
+
+        for t in &auth.tokens {
+            if t.token.is_empty() {
+                Err("empty token in configuration file")?;
+            }
+
+            let scope = match &t.paths {
+                Some(v) => {
+                    for name in v {
+                        if !paths.iter().any(|p| &p.name == name) {
+                            eprintln!("[WARNING] token scope refers to unknown path '{}'.", name);
+                        }
+                    }
+                    Scope::Paths(v.iter().cloned().collect())
+                }
+                None => Scope::All,
+            };
+
+            tokens.insert(t.token.clone(), scope);
+        }
+
+        if let Ok(token) = env::var(TOKEN_ENV)
+            && !token.is_empty()
+        {
+            tokens.insert(token, Scope::All);
+        }
+
+        if auth.generate {
+            let mut bytes = [0u8; 24];
+            getrandom::fill(&mut bytes).map_err(|e| format!("cannot generate token: {}", e))?;
+            let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+            eprintln!("[INFO] generated access token: {}", token);
+            tokens.insert(token, Scope::All);
+        }
+
+        Ok(Self(tokens))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.0.is_empty()
+    }
+
+    fn scope(&self, token: &str) -> Option<&Scope> {
+        // compare against every token so the response time doesn't leak a matching prefix
+        let mut found = None;
+        for (t, s) in &self.0 {
+            if ct_eq(t, token) {
+                found = Some(s);
+            }
+        }
+        found
+    }
+}
+
+fn ct_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Validates the bearer token and attaches its [`Scope`] to the request.
+pub async fn authorize(
+    State(tokens): State<Arc<Tokens>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if !tokens.is_enabled() {
+        req.extensions_mut().insert(Scope::All);
+        return next.run(req).await;
+    }
+
+    let scope = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .and_then(|t| tokens.scope(t.trim()))
+        .cloned();
+
+    match scope {
+        Some(scope) => {
+            req.extensions_mut().insert(scope);
+            next.run(req).await
+        }
+        None => {
+            let mut res = (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({ "error": "missing or invalid bearer token" })),
+            )
+                .into_response();
+            res.headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            res
+        }
+    }
+}
+
+pub fn forbidden(path: &str) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::FORBIDDEN,
+        Json(json!({ "error": format!("token is not allowed to execute '{}'", path) })),
+    )
+}
@@ -0,0 +1,112 @@
+use std::{error::Error, process::ExitCode};
+
+use serde_json::{Value, json};
+
+use crate::{
+    config::{CallArgs, Options, Output},
+    invoke::Call,
+    remote::RemoteProcSignature,
+    requests::{MultiPayload, Text},
+    session::Session,
+    shadow,
+};
+
+/// at least one call failed
+const CALL_FAILED: u8 = 2;
+/// target process is not running
+const NOT_RUNNING: u8 = 3;
+
+/// One-shot mode: injects, calls `args.paths` in order, prints the results to stdout and
+/// ejects. A failed ejection exits with `1` once the results are printed.
+pub fn run(options: &Options, args: &CallArgs) -> Result<ExitCode, Box<dyn Error>> {
+    // bad arguments are reported before touching the target
+    let calls = calls(options, args)?;
+
+    // with `[[targets]]`, only the first one is called
+    let (_, options) = options.targets()?.swap_remove(0);
+    shadow::collect();
+    let Some(session) = Session::open(&options)? else {
+        return Ok(ExitCode::from(NOT_RUNNING));
+    };
+
+    let outcomes = session.procedures.batch(calls, !args.keep_going);
+    let failed = outcomes.iter().any(|o| o.result.is_err());
+
+    match args.output {
+        Output::Text => {
+            for o in outcomes {
+                match o.result {
+                    Ok(v) => println!("{}", v),
+                    Err(e) => eprintln!("[ERROR] '{}' failed: {}", o.path, e),
+                }
+            }
+        }
+        Output::Json => {
+            let results: Vec<Value> = outcomes
+                .into_iter()
+                .map(|o| match o.result {
+                    Ok(v) => json!({
+                        "proc": o.path,
+                        "message": v,
+                        "elapsed_ms": o.elapsed.as_millis(),
+                    }),
+                    Err(e) => json!({
+                        "proc": o.path,
+                        "error": e,
+                        "elapsed_ms": o.elapsed.as_millis(),
+                    }),
+                })
+                .collect();
+
+            println!("{}", serde_json::to_string_pretty(&results)?);
+        }
+    }
+
+    // the results are out whatever happens to the payloads now
+    if let Err(e) = session.close() {
+        eprintln!("[ERROR] cannot eject payload: {}", e);
+        return Ok(ExitCode::FAILURE);
+    }
+
+    Ok(if failed {
+        ExitCode::from(CALL_FAILED)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// `--message` or `--json` goes to `text` paths, `signal` paths are called without one.
+fn calls(options: &Options, args: &CallArgs) -> Result<Vec<Call>, Box<dyn Error>> {
+    let body = match (&args.message, &args.json) {
+        (Some(message), _) => Some(MultiPayload::Text(Text {
+            message: message.clone(),
+        })),
+        (None, Some(json)) => {
+            let value = serde_json::from_str::<Value>(json)?;
+            Some(MultiPayload::from_value(Some(value)).map_err(|e| e.message().to_string())?)
+        }
+        (None, None) => None,
+    };
+
+    args.paths
+        .iter()
+        .map(|name| {
+            let Some(path) = options.paths.iter().find(|p| &p.name == name) else {
+                return Err(format!("'{}' is not a path", name).into());
+            };
+
+            let payload = match (path.signature, &body) {
+                (RemoteProcSignature::Signal, _) => MultiPayload::Signal,
+                (RemoteProcSignature::Text, Some(v)) => v.clone(),
+                (RemoteProcSignature::Text, None) => {
+                    return Err(format!("'{}' takes text, set --message or --json", name).into());
+                }
+            };
+
+            Ok(Call {
+                path: name.clone(),
+                payload,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,225 @@
+use std::{env, error::Error, fs, sync::Arc};
+
+use axum::body::{Body, Bytes, to_bytes};
+use cli_table::{Cell, Style, Table, print_stdout};
+use hyper::{Method, Request, StatusCode, client::conn::http1, header};
+use hyper_util::rt::TokioIo;
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    runtime::Builder,
+};
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{ClientConfig, pki_types::ServerName},
+};
+
+use crate::{
+    auth::TOKEN_ENV,
+    config::{ClientArgs, ClientCommand, Identifier, Options},
+    listener::{Bind, Connection},
+    remote::RemoteProcSignature,
+    tls,
+};
+
+const MAX_RESPONSE: usize = 16 * 1024 * 1024;
+
+/// Runs `epiphyte client` against a server started by another epiphyte process.
+pub fn run(options: &Options, args: &ClientArgs) -> Result<(), Box<dyn Error>> {
+    let server = Server::new(options, args)?;
+    let runtime = Builder::new_current_thread().enable_all().build()?;
+
+    runtime.block_on(async {
+        match &args.command {
+            ClientCommand::List => list(&server).await,
+            ClientCommand::Exec {
+                path,
+                message,
+                json,
+            } => exec(&server, path, message.as_deref(), json.as_deref()).await,
+        }
+    })
+}
+
+struct Server {
+    bind: Bind,
+    tls: Option<Arc<ClientConfig>>,
+    token: Option<String>,
+}
+
+impl Server {
+    /// Without `--server`, the server is assumed to run with the same configuration file.
+    fn new(options: &Options, args: &ClientArgs) -> Result<Self, Box<dyn Error>> {
+        let (bind, https) = match args.server.as_deref().map(|s| s.trim_end_matches('/')) {
+            Some(s) => match (s.strip_prefix("https://"), s.strip_prefix("http://")) {
+                (Some(addr), _) => (addr.parse()?, true),
+                (None, Some(addr)) => (addr.parse()?, false),
+                (None, None) => (s.parse()?, false),
+            },
+            None => (options.bind.clone(), options.tls.is_some()),
+        };
+
+        let tls = if https {
+            let ca = args
+                .ca
+                .clone()
+                .or_else(|| options.tls.as_ref().map(|t| t.cert.clone()))
+                .ok_or("https needs a trusted certificate, set --ca")?;
+            let ca = fs::read(&ca).map_err(|e| format!("cannot read '{}': {}", ca.display(), e))?;
+
+            Some(tls::client_config(&ca)?)
+        } else {
+            None
+        };
+
+        let token = args.token.clone().or_else(|| env::var(TOKEN_ENV).ok());
+
+        Ok(Self { bind, tls, token })
+    }
+
+    fn host(&self) -> &str {
+        match &self.bind {
+            Bind::Tcp(addr) => addr,
+            Bind::Unix(_) => "localhost",
+        }
+    }
+
+    /// Sends one request on a fresh connection, non-JSON bodies are returned as a string
+    /// and unsuccessful responses as errors.
+    async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> Result<Value, Box<dyn Error>> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::HOST, self.host());
+        if let Some(token) = &self.token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = match body {
+            Some(v) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(v.to_string()))?,
+            None => request.body(Body::empty())?,
+        };
+
+        let io = Connection::connect(&self.bind)
+            .await
+            .map_err(|e| format!("cannot connect to '{}': {}", self.bind, e))?;
+
+        let (status, bytes) = match &self.tls {
+            Some(config) => {
+                let host = self.host();
+                let host = host.rsplit_once(':').map_or(host, |(h, _)| h);
+                let name = ServerName::try_from(host.trim_matches(['[', ']']).to_string())?;
+
+                let io = TlsConnector::from(config.clone()).connect(name, io).await?;
+                send(io, request).await?
+            }
+            None => send(io, request).await?,
+        };
+
+        let body = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into()));
+
+        if !status.is_success() {
+            let detail = ["error", "message"]
+                .iter()
+                .find_map(|k| body.get(k))
+                .unwrap_or(&body);
+            let detail = detail.as_str().map_or(detail.to_string(), String::from);
+
+            Err(format!("server responded {}: {}", status, detail))?;
+        }
+
+        Ok(body)
+    }
+}
+
+async fn send<I>(io: I, request: Request<Body>) -> Result<(StatusCode, Bytes), Box<dyn Error>>
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = http1::handshake(TokioIo::new(io)).await?;
+    tokio::spawn(conn);
+
+    let response = sender.send_request(request).await?;
+    let status = response.status();
+    let bytes = to_bytes(Body::new(response.into_body()), MAX_RESPONSE).await?;
+
+    Ok((status, bytes))
+}
+
+async fn procedures(server: &Server) -> Result<Vec<Identifier>, Box<dyn Error>> {
+    let body = server.request(Method::GET, "/procedures", None).await?;
+
+    Ok(serde_json::from_value(body)?)
+}
+
+async fn list(server: &Server) -> Result<(), Box<dyn Error>> {
+    let t = procedures(server)
+        .await?
+        .into_iter()
+        .map(|p| {
+            vec![
+                p.name.cell(),
+                p.symbol.cell(),
+                format!("{:?}", p.signature).cell(),
+            ]
+        })
+        .table()
+        .title(vec![
+            "Path".cell().bold(true),
+            "Symbol".cell().bold(true),
+            "Type".cell().bold(true),
+        ])
+        .bold(true);
+
+    Ok(print_stdout(t)?)
+}
+
+async fn exec(
+    server: &Server,
+    path: &str,
+    message: Option<&str>,
+    json: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let procedures = procedures(server).await?;
+    let Some(procedure) = procedures.iter().find(|p| p.name == path) else {
+        return Err(format!("'{}' is not served by the server", path).into());
+    };
+
+    let body = match (procedure.signature, message, json) {
+        (_, _, Some(json)) => Some(serde_json::from_str::<Value>(json)?),
+        (RemoteProcSignature::Signal, None, None) => None,
+        (RemoteProcSignature::Signal, Some(_), None) => {
+            return Err(format!("'{}' is a signal path and takes no message", path).into());
+        }
+        (RemoteProcSignature::Text, Some(m), None) => Some(json!({ "message": m })),
+        (RemoteProcSignature::Text, None, None) => {
+            return Err(format!("'{}' takes text, set --message or --json", path).into());
+        }
+    };
+
+    let res = server
+        .request(Method::POST, &format!("/execute/{}", path), body)
+        .await?;
+
+    let t = vec![vec![
+        path.cell(),
+        res["message"].as_str().unwrap_or_default().cell(),
+        res["elapsed_ms"].to_string().cell(),
+    ]]
+    .table()
+    .title(vec![
+        "Path".cell().bold(true),
+        "Result".cell().bold(true),
+        "Elapsed (ms)".cell().bold(true),
+    ])
+    .bold(true);
+
+    Ok(print_stdout(t)?)
+}
@@ -1,18 +1,79 @@
-use std::{fs, path::PathBuf};
+use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
-use crate::remote::RemoteProcSignature;
+use crate::{
+    auth::Auth,
+    launch::Launch,
+    listener::Bind,
+    remote::RemoteProcSignature,
+    target::{Pick, Selector},
+    tls::Tls,
+};
 
 #[derive(Deserialize, Default)]
 struct Config {
     target_name: Option<String>,
     payload_path: Option<PathBuf>,
     port: Option<u16>,
+    bind: Option<Bind>,
     timeout: Option<u64>,
     paths: Option<Vec<Map>>,
+    teardown: Option<String>,
+    payloads: Option<Vec<PayloadEntry>>,
+    auth: Option<Auth>,
+    tls: Option<Tls>,
+    target: Option<Selector>,
+    launch: Option<Launch>,
+    targets: Option<Vec<TargetEntry>>,
+    on_exit: Option<OnExit>,
+    on_existing: Option<OnExisting>,
+}
+
+/// One of several targets, each injected with its own copy of the payload. The criteria
+/// of [`Selector`] are spelled out, serde ignores `deny_unknown_fields` on flattened
+/// structs and a misspelled criterion would match more processes than intended.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetEntry {
+    pub id: String,
+    pub target_name: Option<String>,
+    pub pid: Option<u32>,
+    pub name: Option<String>,
+    pub name_regex: Option<String>,
+    pub cmdline: Option<String>,
+    pub cmdline_regex: Option<String>,
+    pub parent_pid: Option<u32>,
+    pub pick: Option<Pick>,
+    pub launch: Option<Launch>,
+}
+
+impl TargetEntry {
+    fn selector(&self) -> Selector {
+        Selector {
+            name_contains: self.target_name.clone(),
+            pid: self.pid,
+            name: self.name.clone(),
+            name_regex: self.name_regex.clone(),
+            cmdline: self.cmdline.clone(),
+            cmdline_regex: self.cmdline_regex.clone(),
+            parent_pid: self.parent_pid,
+            pick: self.pick,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PayloadEntry {
+    /// default: file name without extension
+    name: Option<String>,
+    path: PathBuf,
+    #[serde(default)]
+    paths: Vec<Map>,
+    teardown: Option<String>,
 }
 
 #[derive(Clone, Deserialize, Default)]
@@ -34,10 +95,16 @@ impl Config {
 #[derive(Parser)]
 #[command(about, long_about = None, version = env!("EPIPHYTE_BUILD_VERSION"))]
 struct Cli {
-    /// overrides config
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// substring of the target process name, overrides config
     #[arg(short = 't', long)]
     target_name: Option<String>,
 
+    #[command(flatten)]
+    target: Selector,
+
     /// overrides config
     #[arg(short, long)]
     payload_path: Option<PathBuf>,
@@ -46,6 +113,51 @@ struct Cli {
     #[arg(long)]
     port: Option<u16>,
 
+    /// host:port, [::1]:port or unix:/path/to.sock, overrides port and config
+    #[arg(short, long)]
+    bind: Option<Bind>,
+
+    /// generate an access token on startup, overrides config
+    #[arg(long)]
+    generate_token: bool,
+
+    /// read JSON lines requests from stdin instead of serving REST
+    #[arg(long)]
+    stdio: bool,
+
+    /// interactive prompt after injection, alongside REST unless --no-server is set
+    #[arg(long, conflicts_with = "stdio")]
+    repl: bool,
+
+    /// don't serve REST, only the REPL
+    #[arg(long, requires = "repl")]
+    no_server: bool,
+
+    /// serve REST from a separate, unprivileged process that never touches the target
+    #[arg(long, conflicts_with_all = ["stdio", "repl"])]
+    split: bool,
+
+    /// set by the split mode broker on the process it starts
+    #[arg(long, hide = true)]
+    front_end: bool,
+
+    /// keep serving until the target starts, for SECONDS at most if given
+    #[arg(long, value_name = "SECONDS", num_args = 0..=1, conflicts_with = "split")]
+    wait: Option<Option<u64>>,
+
+    /// what to do when the target exits (default: shutdown), overrides config
+    #[arg(long, value_enum)]
+    on_exit: Option<OnExit>,
+
+    /// what to do when the payload is already loaded in the target (default: error),
+    /// overrides config
+    #[arg(long, value_enum)]
+    on_existing: Option<OnExisting>,
+
+    /// inject the payload again whenever it is rebuilt
+    #[arg(long)]
+    watch: bool,
+
     /// default: ./config.toml
     #[arg(short, long)]
     config_path: Option<PathBuf>,
@@ -55,30 +167,196 @@ struct Cli {
     verbose: bool,
 }
 
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// print OpenAPI document of configured paths and exit
+    Openapi,
+    /// inject, call paths in order, print the results and eject
+    Call(CallArgs),
+    /// talk to a running epiphyte server
+    Client(ClientArgs),
+    /// list running processes that could be targeted
+    Ps(PsArgs),
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct PsArgs {
+    /// substring of the process name, like target_name
+    pub filter: Option<String>,
+
+    #[arg(short, long, value_enum, default_value_t)]
+    pub output: Output,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct CallArgs {
+    /// path names, called in order
+    #[arg(required = true)]
+    pub paths: Vec<String>,
+
+    /// argument of text paths
+    #[arg(short, long, conflicts_with = "json")]
+    pub message: Option<String>,
+
+    /// body of text paths, as sent to /execute
+    #[arg(long)]
+    pub json: Option<String>,
+
+    /// keep calling the remaining paths after a failure
+    #[arg(short, long)]
+    pub keep_going: bool,
+
+    #[arg(short, long, value_enum, default_value_t)]
+    pub output: Output,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ClientArgs {
+    /// http://host:port, https://host:port or unix:/path/to.sock (default: configured bind)
+    #[arg(short, long)]
+    pub server: Option<String>,
+
+    /// bearer token (default: EPIPHYTE_TOKEN environment variable)
+    #[arg(long)]
+    pub token: Option<String>,
+
+    /// PEM certificates trusted for https (default: configured tls.cert)
+    #[arg(long)]
+    pub ca: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: ClientCommand,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum ClientCommand {
+    /// list paths served by the server
+    List,
+    /// execute a path, the body is built from its signature
+    Exec {
+        path: String,
+
+        /// argument of text paths
+        #[arg(short, long, conflicts_with = "json")]
+        message: Option<String>,
+
+        /// raw body, as sent to /execute
+        #[arg(long)]
+        json: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OnExit {
+    /// stop serving and exit
+    #[default]
+    Shutdown,
+    /// keep serving, calls fail until epiphyte is restarted
+    Detach,
+    /// wait for a matching process (or launch it) and inject it again
+    Recover,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OnExisting {
+    /// resolve procedures against the loaded payload
+    Reuse,
+    /// eject the loaded payload, then inject
+    Replace,
+    /// refuse to inject a second copy
+    #[default]
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Output {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Id of the only target when `[[targets]]` is not set.
+pub const DEFAULT_TARGET: &str = "default";
+
 #[derive(Clone, Debug, Default)]
 pub struct Options {
-    pub target_name: String,
-    pub payload_path: PathBuf,
-    pub port: u16,
+    pub command: Option<Command>,
+    pub target: Selector,
+    pub launch: Option<Launch>,
+    /// `[[targets]]`, replacing `target` and `launch` when set
+    pub targets: Vec<TargetEntry>,
+    /// injected in order, ejected in reverse
+    pub payloads: Vec<Payload>,
+    pub bind: Bind,
+    pub tls: Option<Tls>,
     pub timeout: u64,
+    /// of every payload
     pub paths: Vec<Identifier>,
+    /// unqualified names of paths only one of several payloads defines
+    pub aliases: HashMap<String, String>,
+    pub auth: Auth,
+    /// `Some` with `--wait`, holding its deadline if any
+    pub wait: Option<Option<Duration>>,
+    pub on_exit: OnExit,
+    pub on_existing: OnExisting,
+    pub is_watching: bool,
+    pub config_path: PathBuf,
+    pub is_stdio: bool,
+    pub is_repl: bool,
+    pub is_serving: bool,
+    pub is_split: bool,
+    pub is_front_end: bool,
     pub is_verbose: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Identifier {
     pub name: String,
     pub symbol: String,
     pub signature: RemoteProcSignature,
 }
 
+/// Payload binary and the paths it exports.
+#[derive(Clone, Debug)]
+pub struct Payload {
+    pub name: String,
+    pub path: PathBuf,
+    /// named `{payload}/{path}` with `[[payloads]]`
+    pub paths: Vec<Identifier>,
+    /// `void(void)` export called before ejecting
+    pub teardown: Option<String>,
+}
+
+fn identifiers(maps: &[Map], prefix: Option<&str>) -> Vec<Identifier> {
+    maps.iter()
+        .map(|x| {
+            let name = x.name.as_str();
+            Identifier {
+                name: match prefix {
+                    Some(p) => format!("{}/{}", p, name),
+                    None => name.into(),
+                },
+                symbol: x.symbol.clone().unwrap_or(name.into()),
+                signature: x.signature.unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+fn file_stem(path: &std::path::Path) -> String {
+    path.file_stem()
+        .map_or("payload".into(), |v| v.to_string_lossy().into())
+}
+
 impl Options {
     pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
         let cli = Cli::parse();
 
-        let config_path = cli.config_path.unwrap_or_else(|| {
-            println!("[WARNING] configuration file path is not set.");
-            println!("[WARNING] looking for ./config.toml.");
+        let config_path = cli.config_path.clone().unwrap_or_else(|| {
+            eprintln!("[WARNING] configuration file path is not set.");
+            eprintln!("[WARNING] looking for ./config.toml.");
             "config.toml".into()
         });
         let config = match Config::read_config(&config_path) {
@@ -93,48 +371,215 @@ impl Options {
             }
         };
 
-        let target_name = cli.target_name.or(config.target_name).ok_or(
-            "target name is defined in neither configuration file nor command line arguments.",
-        )?;
+        Self::resolve(cli, config, config_path)
+    }
 
-        let payload_path = cli.payload_path.or(config.payload_path).ok_or(
-            "payload path is defined in neither configuration file nor command line arguments",
-        )?;
+    /// Resolves the configuration file again with the same command line. Unlike at
+    /// startup, a file that cannot be read is an error.
+    pub fn reload(&self) -> Result<Self, Box<dyn std::error::Error>> {
+        let cli = Cli::parse();
+        let config = Config::read_config(&self.config_path).map_err(|e| {
+            format!(
+                "cannot read config in '{}': {}",
+                self.config_path.display(),
+                e
+            )
+        })?;
 
-        let port = cli.port.or(config.port).unwrap_or(8070);
+        Self::resolve(cli, config, self.config_path.clone())
+    }
+
+    fn resolve(
+        cli: Cli,
+        config: Config,
+        config_path: PathBuf,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut target = cli.target.or(config.target.unwrap_or_default());
+        target.name_contains = cli.target_name.or(config.target_name);
+
+        let bind = match (cli.bind, cli.port) {
+            (Some(v), _) => v,
+            (None, Some(port)) => Bind::loopback(port),
+            (None, None) => config
+                .bind
+                .unwrap_or_else(|| config.port.map(Bind::loopback).unwrap_or_default()),
+        };
 
         let timeout = config.timeout.unwrap_or(500);
 
-        let paths = match config.paths {
-            Some(v) => v,
-            None => {
-                println!("[WARNING] no paths defined in configuration file.");
-                Default::default()
+        // a payload path on the command line stands for the top-level paths
+        let (payloads, aliases) = match (cli.payload_path, config.payloads) {
+            (None, Some(entries)) if !entries.is_empty() => {
+                let mut payloads: Vec<Payload> = Vec::with_capacity(entries.len());
+                for entry in entries {
+                    let name = entry.name.unwrap_or_else(|| file_stem(&entry.path));
+                    if name.is_empty() || name.contains('/') {
+                        Err(format!(
+                            "payload name '{}' must be non-empty and not contain '/'",
+                            name
+                        ))?;
+                    }
+                    if payloads.iter().any(|p| p.name == name) {
+                        Err(format!("payload name '{}' is defined more than once", name))?;
+                    }
+                    payloads.push(Payload {
+                        paths: identifiers(&entry.paths, Some(&name)),
+                        name,
+                        path: entry.path,
+                        teardown: entry.teardown,
+                    });
+                }
+                let aliases = aliases(&payloads);
+                (payloads, aliases)
+            }
+            (cli_path, _) => {
+                let paths = match config.paths {
+                    Some(v) => v,
+                    None => {
+                        eprintln!("[WARNING] no paths defined in configuration file.");
+                        Default::default()
+                    }
+                };
+                let payloads = cli_path
+                    .or(config.payload_path)
+                    .map(|path| Payload {
+                        name: file_stem(&path),
+                        path,
+                        paths: identifiers(&paths, None),
+                        teardown: config.teardown,
+                    })
+                    .into_iter()
+                    .collect();
+                // path names are not qualified, nothing to alias
+                (payloads, HashMap::new())
             }
         };
-        let paths = paths
-            .iter()
-            .map(|x| {
-                let name = x.name.as_str();
-                Identifier {
-                    name: name.into(),
-                    symbol: x.symbol.clone().unwrap_or(name.into()),
-                    signature: x.signature.unwrap_or_default(),
-                }
-            })
-            .collect();
+        let paths: Vec<Identifier> = payloads.iter().flat_map(|p| p.paths.clone()).collect();
+
+        let mut auth = config.auth.unwrap_or_default();
+        auth.generate |= cli.generate_token;
+
+        // the front end only knows the one target the broker holds
+        let targets = config.targets.unwrap_or_default();
+        if cli.split && !targets.is_empty() {
+            Err("[[targets]] cannot be combined with --split")?;
+        }
 
         let is_verbose = cli.verbose;
 
         let res = Self {
-            target_name,
-            payload_path,
-            port,
+            command: cli.command,
+            target,
+            launch: config.launch,
+            targets,
+            payloads,
+            bind,
+            tls: config.tls,
             timeout,
             paths,
+            aliases,
+            auth,
+            wait: cli.wait.map(|v| v.map(Duration::from_secs)),
+            on_exit: cli.on_exit.or(config.on_exit).unwrap_or_default(),
+            on_existing: cli.on_existing.or(config.on_existing).unwrap_or_default(),
+            is_watching: cli.watch,
+            config_path,
+            is_stdio: cli.stdio,
+            is_repl: cli.repl,
+            // the broker serves through the front end
+            is_serving: !cli.stdio && !cli.no_server && (!cli.split || cli.front_end),
+            is_split: cli.split && !cli.front_end,
+            is_front_end: cli.front_end,
             is_verbose,
         };
 
         Ok(res)
     }
+
+    pub fn target(&self) -> Result<&Selector, Box<dyn std::error::Error>> {
+        if !self.target.is_set() {
+            Err("target is defined in neither configuration file nor command line arguments.")?;
+        }
+
+        Ok(&self.target)
+    }
+
+    /// Options of every target, keyed by id. The first one also answers requests that don't
+    /// name a target.
+    pub fn targets(&self) -> Result<Vec<(String, Options)>, Box<dyn std::error::Error>> {
+        if self.targets.is_empty() {
+            return Ok(vec![(DEFAULT_TARGET.into(), self.clone())]);
+        }
+
+        let mut res: Vec<(String, Options)> = Vec::with_capacity(self.targets.len());
+        for entry in &self.targets {
+            if res.iter().any(|(id, _)| *id == entry.id) {
+                Err(format!(
+                    "target id '{}' is defined more than once",
+                    entry.id
+                ))?;
+            }
+
+            let options = Self {
+                target: entry.selector(),
+                launch: entry.launch.clone(),
+                targets: Vec::new(),
+                ..self.clone()
+            };
+            res.push((entry.id.clone(), options));
+        }
+
+        Ok(res)
+    }
+
+    pub fn payloads(&self) -> Result<&[Payload], Box<dyn std::error::Error>> {
+        if self.payloads.is_empty() {
+            Err(
+                "payload path is defined in neither configuration file nor command line arguments",
+            )?;
+        }
+
+        Ok(&self.payloads)
+    }
+
+    pub fn payload_paths(&self) -> Vec<PathBuf> {
+        self.payloads.iter().map(|p| p.path.clone()).collect()
+    }
+}
+
+/// Maps the unqualified names of `[[payloads]]` paths to their payload, reporting the ones
+/// defined by several payloads, which must be called with their payload name.
+fn aliases(payloads: &[Payload]) -> HashMap<String, String> {
+    let mut owners: HashMap<&str, Vec<&Payload>> = HashMap::new();
+    for payload in payloads {
+        for path in &payload.paths {
+            let name = &path.name[payload.name.len() + 1..];
+            let owners = owners.entry(name).or_default();
+            // a payload may define the same path twice
+            if owners.last().is_none_or(|p| p.name != payload.name) {
+                owners.push(payload);
+            }
+        }
+    }
+
+    let mut res = HashMap::new();
+    for (name, owners) in owners {
+        match owners.as_slice() {
+            [payload] => {
+                res.insert(name.to_string(), format!("{}/{}", payload.name, name));
+            }
+            owners => {
+                let names: Vec<&str> = owners.iter().map(|p| p.name.as_str()).collect();
+                eprintln!(
+                    "[WARNING] path '{}' is defined by payloads {}, call it as '{}/{}'.",
+                    name,
+                    names.join(", "),
+                    names[0],
+                    name
+                );
+            }
+        }
+    }
+
+    res
 }
@@ -0,0 +1,239 @@
+use std::time::{Duration, Instant};
+
+use tokio::{
+    sync::mpsc,
+    time::{MissedTickBehavior, interval},
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use crate::{
+    auth::Scope,
+    remote::RemoteProcSignature,
+    requests::{MultiPayload, Text},
+    server::{self, AppState, SessionState},
+};
+
+pub mod proto {
+    tonic::include_proto!("epiphyte");
+}
+
+use proto::{
+    Event, EventKind, ExecuteRequest, ExecuteResponse, InfoRequest, InfoResponse,
+    ListProceduresRequest, ListProceduresResponse, Procedure, Signature, State, SubscribeRequest,
+    epiphyte_server::{Epiphyte, EpiphyteServer},
+};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// gRPC service for `proto/epiphyte.proto`, mounted on the REST router so it shares its
+/// listener, TLS and token middleware.
+pub struct Service {
+    state: AppState,
+}
+
+pub fn service(state: AppState) -> EpiphyteServer<Service> {
+    EpiphyteServer::new(Service { state })
+}
+
+impl Service {
+    fn authorize<T>(&self, request: &Request<T>, proc: &str) -> Result<(), Status> {
+        match request.extensions().get::<Scope>() {
+            Some(scope) if scope.allows(proc) => Ok(()),
+            _ => Err(Status::permission_denied(format!(
+                "token is not allowed to execute '{}'",
+                proc
+            ))),
+        }
+    }
+}
+
+async fn execute(state: &AppState, call: &ExecuteRequest) -> Result<ExecuteResponse, Status> {
+    if !state
+        .paths
+        .read()
+        .unwrap()
+        .iter()
+        .any(|p| p.name == call.proc)
+    {
+        return Err(Status::not_found(format!("'{}' is not a path", call.proc)));
+    }
+
+    if let Some(reason) = state.status.read().unwrap().unavailable() {
+        return Err(Status::unavailable(reason));
+    }
+
+    let payload = match &call.message {
+        Some(message) => MultiPayload::Text(Text {
+            message: message.clone(),
+        }),
+        None => MultiPayload::Signal,
+    };
+
+    let start = Instant::now();
+
+    match state.invoker.call(call.proc.clone(), payload).await {
+        Ok(message) => Ok(ExecuteResponse {
+            message,
+            elapsed_ms: start.elapsed().as_millis() as u64,
+            event: None,
+        }),
+        Err(e) => Err(Status::internal(e)),
+    }
+}
+
+fn event(event: &server::Event) -> Event {
+    Event {
+        seq: event.seq,
+        kind: match event.kind {
+            server::EventKind::Exited => EventKind::Exited,
+            server::EventKind::Waiting => EventKind::Waiting,
+            server::EventKind::Failed => EventKind::Failed,
+            server::EventKind::Recovered => EventKind::Recovered,
+        }
+        .into(),
+        message: event.message.clone(),
+    }
+}
+
+#[tonic::async_trait]
+impl Epiphyte for Service {
+    async fn info(&self, _: Request<InfoRequest>) -> Result<Response<InfoResponse>, Status> {
+        let status = self.state.status.read().unwrap().clone();
+        let info = status.info.unwrap_or_default();
+
+        Ok(Response::new(InfoResponse {
+            base_name: info.base_name,
+            exec_path: info.exec_path,
+            pid: info.pid,
+            state: match status.state {
+                SessionState::Waiting => State::Waiting,
+                SessionState::Attached => State::Attached,
+                SessionState::Detached => State::Detached,
+            }
+            .into(),
+            restarts: status.restarts,
+            exit_code: status.exit_code,
+            events: status.events.iter().map(event).collect(),
+        }))
+    }
+
+    async fn list_procedures(
+        &self,
+        _: Request<ListProceduresRequest>,
+    ) -> Result<Response<ListProceduresResponse>, Status> {
+        let procedures = self
+            .state
+            .paths
+            .read()
+            .unwrap()
+            .iter()
+            .map(|p| Procedure {
+                name: p.name.clone(),
+                symbol: p.symbol.clone(),
+                signature: match p.signature {
+                    RemoteProcSignature::Signal => Signature::Signal,
+                    RemoteProcSignature::Text => Signature::Text,
+                }
+                .into(),
+            })
+            .collect();
+
+        Ok(Response::new(ListProceduresResponse { procedures }))
+    }
+
+    async fn execute(
+        &self,
+        request: Request<ExecuteRequest>,
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        let proc = self.state.canonical(&request.get_ref().proc);
+        self.authorize(&request, &proc)?;
+        let call = ExecuteRequest {
+            proc,
+            ..request.into_inner()
+        };
+
+        execute(&self.state, &call).await.map(Response::new)
+    }
+
+    type SubscribeStream = ReceiverStream<Result<ExecuteResponse, Status>>;
+
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let Some(mut call) = request.get_ref().call.clone() else {
+            return Err(Status::invalid_argument("call is not set"));
+        };
+        call.proc = self.state.canonical(&call.proc);
+        self.authorize(&request, &call.proc)?;
+
+        let period = match request.get_ref().interval_ms {
+            0 => DEFAULT_INTERVAL,
+            ms => Duration::from_millis(ms.into()),
+        };
+
+        let state = self.state.clone();
+        let (tx, rx) = mpsc::channel(16);
+
+        tokio::spawn(async move {
+            let mut ticker = interval(period);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // only events from now on are streamed
+            let mut seen = state
+                .status
+                .read()
+                .unwrap()
+                .events
+                .last()
+                .map_or(0, |e| e.seq);
+
+            loop {
+                ticker.tick().await;
+
+                let (events, recovering) = {
+                    let status = state.status.read().unwrap();
+                    let events: Vec<Event> = status
+                        .events
+                        .iter()
+                        .filter(|e| e.seq > seen)
+                        .map(event)
+                        .collect();
+                    // an exit not followed by any other event is never recovered from
+                    let recovering = status.state == SessionState::Detached
+                        && status
+                            .events
+                            .last()
+                            .is_some_and(|e| e.kind != server::EventKind::Exited);
+                    (events, recovering)
+                };
+
+                for event in events {
+                    seen = event.seq;
+                    let res = ExecuteResponse {
+                        event: Some(event),
+                        ..Default::default()
+                    };
+                    if tx.send(Ok(res)).await.is_err() {
+                        return;
+                    }
+                }
+
+                // calls resume once the target is recovered
+                if recovering {
+                    continue;
+                }
+
+                let res = execute(&state, &call).await;
+                let failed = res.is_err();
+
+                // stops once the client goes away or the procedure fails
+                if tx.send(res).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant},
+};
+
+use dll_syringe::{Syringe, process::BorrowedProcessModule};
+use tokio::{sync::oneshot, time::timeout};
+
+use crate::{
+    config::Options,
+    payload::Metadata,
+    remote::{RemoteProcContainer, RemoteProcSignature, ScopedRemoteString},
+    requests::MultiPayload,
+};
+
+const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+pub type Reply = Result<String, String>;
+
+#[derive(Clone)]
+pub struct Call {
+    pub path: String,
+    pub payload: MultiPayload,
+}
+
+pub struct Outcome {
+    pub path: String,
+    pub result: Reply,
+    pub elapsed: Duration,
+}
+
+/// Work sent from front ends to the invocation thread, which owns the remote procedures.
+pub enum Request {
+    Call(Call, oneshot::Sender<Reply>),
+    Batch {
+        calls: Vec<Call>,
+        stop_on_error: bool,
+        reply: oneshot::Sender<Vec<Outcome>>,
+    },
+    /// Injects the payload again from disk, ejecting it first if needed.
+    Reload(oneshot::Sender<Reply>),
+    Eject(oneshot::Sender<Reply>),
+    /// Call on the target with this id, the others go to the first target.
+    Target {
+        id: String,
+        call: Call,
+        reply: oneshot::Sender<Reply>,
+    },
+    /// Same call on every target, replies are keyed by target id.
+    Broadcast {
+        call: Call,
+        reply: oneshot::Sender<Vec<(String, Reply)>>,
+    },
+    /// Sent by the exit watcher of the session, handled by the invocation loop itself.
+    Exited {
+        pid: u32,
+        code: Option<u32>,
+    },
+}
+
+/// Front end side of the invocation channel.
+#[derive(Clone)]
+pub struct Invoker {
+    tx: mpsc::Sender<Request>,
+    pauses: Arc<Pauses>,
+}
+
+#[derive(Default)]
+struct Pauses {
+    active: AtomicUsize,
+    /// ended so far, a deadline that ran out across a pause is extended too
+    ended: AtomicU64,
+}
+
+/// Holds off reply deadlines until dropped, see [`Invoker::pause`].
+pub struct Pause(Arc<Pauses>);
+
+impl Drop for Pause {
+    fn drop(&mut self) {
+        self.0.ended.fetch_add(1, Ordering::SeqCst);
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Invoker {
+    pub fn new(tx: mpsc::Sender<Request>) -> Self {
+        Self {
+            tx,
+            pauses: Default::default(),
+        }
+    }
+
+    /// Keeps requests waiting on the channel from timing out while the invocation thread is
+    /// busy with longer work, like reloading the payloads.
+    pub fn pause(&self) -> Pause {
+        self.pauses.active.fetch_add(1, Ordering::SeqCst);
+        Pause(self.pauses.clone())
+    }
+
+    pub async fn call(&self, path: String, payload: MultiPayload) -> Reply {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(Request::Call(Call { path, payload }, reply_tx))?;
+
+        self.wait(reply_rx, REPLY_TIMEOUT).await?
+    }
+
+    pub async fn call_target(&self, id: String, path: String, payload: MultiPayload) -> Reply {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(Request::Target {
+            id,
+            call: Call { path, payload },
+            reply: reply_tx,
+        })?;
+
+        self.wait(reply_rx, REPLY_TIMEOUT).await?
+    }
+
+    /// Calls every target one after another, so the deadline is per target.
+    pub async fn broadcast(
+        &self,
+        path: String,
+        payload: MultiPayload,
+        targets: usize,
+    ) -> Result<Vec<(String, Reply)>, String> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(Request::Broadcast {
+            call: Call { path, payload },
+            reply: reply_tx,
+        })?;
+
+        self.wait(reply_rx, REPLY_TIMEOUT * targets.max(1) as u32)
+            .await
+    }
+
+    /// Waits for the reply without a deadline, for front ends that run calls one by one.
+    pub fn call_blocking(&self, path: String, payload: MultiPayload) -> Reply {
+        self.blocking(|reply| Request::Call(Call { path, payload }, reply))
+    }
+
+    pub fn reload_blocking(&self) -> Reply {
+        self.blocking(Request::Reload)
+    }
+
+    pub fn eject_blocking(&self) -> Reply {
+        self.blocking(Request::Eject)
+    }
+
+    pub async fn batch(
+        &self,
+        calls: Vec<Call>,
+        stop_on_error: bool,
+    ) -> Result<Vec<Outcome>, String> {
+        let deadline = REPLY_TIMEOUT * calls.len().max(1) as u32;
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(Request::Batch {
+            calls,
+            stop_on_error,
+            reply: reply_tx,
+        })?;
+
+        self.wait(reply_rx, deadline).await
+    }
+
+    pub fn exited(&self, pid: u32, code: Option<u32>) {
+        // the loop is gone already when shutting down
+        let _ = self.send(Request::Exited { pid, code });
+    }
+
+    fn blocking(&self, request: impl FnOnce(oneshot::Sender<Reply>) -> Request) -> Reply {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(request(reply_tx))?;
+
+        reply_rx
+            .blocking_recv()
+            .map_err(|_| "invocation loop dropped the request".to_string())?
+    }
+
+    fn send(&self, request: Request) -> Result<(), String> {
+        self.tx
+            .send(request)
+            .map_err(|_| "invocation loop is not running".to_string())
+    }
+
+    /// Waits up to `deadline` for the reply, and again as long as a pause holds it off.
+    async fn wait<T>(
+        &self,
+        mut reply_rx: oneshot::Receiver<T>,
+        deadline: Duration,
+    ) -> Result<T, String> {
+        loop {
+            let ended = self.pauses.ended.load(Ordering::SeqCst);
+
+            match timeout(deadline, &mut reply_rx).await {
+                Ok(Ok(v)) => return Ok(v),
+                Ok(Err(_)) => return Err("invocation loop dropped the request".into()),
+                Err(_)
+                    if self.pauses.active.load(Ordering::SeqCst) > 0
+                        || self.pauses.ended.load(Ordering::SeqCst) != ended => {}
+                Err(_) => return Err("timed out waiting on channel".into()),
+            }
+        }
+    }
+}
+
+/// Path names of the configured paths, with the other names they can be called by.
+#[derive(Clone, Debug, Default)]
+pub struct Routes {
+    names: HashSet<String>,
+    /// unqualified names of paths only one payload defines
+    aliases: HashMap<String, String>,
+    /// path names by export symbol, for routes from before they were keyed by path name.
+    /// `None` when more than one path exports the symbol.
+    symbols: HashMap<String, Option<String>>,
+}
+
+impl Routes {
+    pub fn new(options: &Options) -> Self {
+        let mut symbols = HashMap::new();
+        for path in options.paths.iter().filter(|p| p.symbol != p.name) {
+            symbols
+                .entry(path.symbol.clone())
+                .and_modify(|v| *v = None)
+                .or_insert_with(|| Some(path.name.clone()));
+        }
+
+        Self {
+            names: options.paths.iter().map(|p| p.name.clone()).collect(),
+            aliases: options.aliases.clone(),
+            symbols,
+        }
+    }
+
+    /// Path name `name` stands for, as a path name itself, an alias or, deprecated, the
+    /// export symbol of a path. Token scopes are checked against it. Unknown names are
+    /// returned as is.
+    pub fn canonical<'a>(&'a self, name: &'a str) -> &'a str {
+        if self.names.contains(name) {
+            return name;
+        }
+
+        self.aliases
+            .get(name)
+            .or_else(|| self.symbols.get(name)?.as_ref())
+            .map_or(name, String::as_str)
+    }
+}
+
+/// Remote procedures of the injected payloads, keyed by path name.
+pub struct Procedures {
+    pid: u32,
+    table: HashMap<String, RemoteProcContainer>,
+    routes: Routes,
+}
+
+impl Procedures {
+    pub fn resolve(
+        syringe: &Syringe,
+        module: BorrowedProcessModule<'_>,
+        metadata: HashMap<String, Metadata>,
+        pid: u32,
+    ) -> Self {
+        let table = metadata
+            .into_iter()
+            .filter_map(|(s, m)| {
+                if s != "DllMain"
+                    && m.is_valid()
+                    && let Some(sig) = m.signature
+                    && let Some(name) = m.symbol
+                {
+                    let procedure = match sig {
+                        RemoteProcSignature::Signal => RemoteProcContainer::Signal(unsafe {
+                            syringe.get_raw_procedure(module, &s).ok()??
+                        }),
+                        RemoteProcSignature::Text => RemoteProcContainer::Text(unsafe {
+                            syringe.get_raw_procedure(module, &s).ok()??
+                        }),
+                    };
+
+                    Some((name, procedure))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            pid,
+            table,
+            routes: Routes::default(),
+        }
+    }
+
+    pub fn empty(pid: u32) -> Self {
+        Self {
+            pid,
+            table: HashMap::new(),
+            routes: Routes::default(),
+        }
+    }
+
+    /// Adds the procedures of another payload injected into the same process.
+    pub fn extend(&mut self, other: Procedures) {
+        self.table.extend(other.table);
+    }
+
+    pub fn with_routes(mut self, routes: Routes) -> Self {
+        self.routes = routes;
+        self
+    }
+
+    /// Looks up a path name, an alias or, deprecated, the export symbol of a path.
+    fn get(&self, path: &str) -> Option<&RemoteProcContainer> {
+        self.table.get(self.routes.canonical(path))
+    }
+
+    pub fn call(&self, path: &str, payload: MultiPayload) -> Reply {
+        match (self.get(path), payload) {
+            (Some(RemoteProcContainer::Signal(proc)), MultiPayload::Signal) => {
+                proc.call().map_err(|e| e.to_string())?;
+                Ok("SACK".into())
+            }
+            (Some(RemoteProcContainer::Text(proc)), MultiPayload::Text(text)) => {
+                let outgoing_msg =
+                    ScopedRemoteString::new(self.pid, &text.message).map_err(|e| e.to_string())?;
+
+                let res = proc
+                    .call(outgoing_msg.get_addr())
+                    .map_err(|e| e.to_string())?;
+                let res =
+                    ScopedRemoteString::from_remote(self.pid, res).map_err(|e| e.to_string())?;
+                let s = res.read_remote().map_err(|e| e.to_string())?;
+
+                Ok(format!("TACK. {}.", s))
+            }
+            _ => Err("Invalid payload".into()),
+        }
+    }
+
+    pub fn batch(&self, calls: Vec<Call>, stop_on_error: bool) -> Vec<Outcome> {
+        let mut outcomes = Vec::with_capacity(calls.len());
+
+        for call in calls {
+            let start = Instant::now();
+            let result = self.call(&call.path, call.payload);
+            let failed = result.is_err();
+
+            outcomes.push(Outcome {
+                path: call.path,
+                result,
+                elapsed: start.elapsed(),
+            });
+
+            if failed && stop_on_error {
+                break;
+            }
+        }
+
+        outcomes
+    }
+}
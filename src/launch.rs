@@ -0,0 +1,93 @@
+use std::{
+    collections::HashMap, error::Error, mem, os::windows::process::CommandExt, path::PathBuf,
+    process::Command,
+};
+
+use dll_syringe::process::OwnedProcess;
+use serde::Deserialize;
+use windows::Win32::{
+    Foundation::CloseHandle,
+    System::{
+        Diagnostics::ToolHelp::{
+            CreateToolhelp32Snapshot, TH32CS_SNAPTHREAD, THREADENTRY32, Thread32First, Thread32Next,
+        },
+        Threading::{CREATE_SUSPENDED, OpenThread, ResumeThread, THREAD_SUSPEND_RESUME},
+    },
+};
+
+/// Target started by epiphyte when no running process matches.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Launch {
+    pub program: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    /// added to the environment of epiphyte
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// keeps the target from running until the payload is injected (default: true)
+    pub suspended: Option<bool>,
+    /// signal paths called in order after injection, before the target resumes
+    #[serde(default)]
+    pub init: Vec<String>,
+}
+
+impl Launch {
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.unwrap_or(true)
+    }
+
+    pub fn spawn(&self) -> Result<OwnedProcess, Box<dyn Error>> {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args).envs(&self.env);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        if self.is_suspended() {
+            command.creation_flags(CREATE_SUSPENDED.0);
+        }
+
+        let child = command
+            .spawn()
+            .map_err(|e| format!("cannot launch '{}': {}", self.program.display(), e))?;
+
+        eprintln!(
+            "[INFO] launched '{}'{}, pid: {}.",
+            self.program.display(),
+            if self.is_suspended() {
+                " suspended"
+            } else {
+                ""
+            },
+            child.id()
+        );
+
+        Ok(OwnedProcess::from_child(child))
+    }
+}
+
+/// Resumes every thread of a process started with `CREATE_SUSPENDED`.
+pub fn resume(pid: u32) -> windows::core::Result<()> {
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0)?;
+
+        let mut entry = THREADENTRY32 {
+            dwSize: mem::size_of::<THREADENTRY32>() as u32,
+            ..Default::default()
+        };
+        let mut res = Thread32First(snapshot, &mut entry);
+        while res.is_ok() {
+            if entry.th32OwnerProcessID == pid
+                && let Ok(thread) = OpenThread(THREAD_SUSPEND_RESUME, false, entry.th32ThreadID)
+            {
+                // threads that aren't suspended are left as they are
+                ResumeThread(thread);
+                let _ = CloseHandle(thread);
+            }
+            res = Thread32Next(snapshot, &mut entry);
+        }
+
+        CloseHandle(snapshot)
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::Shutdown,
+    os::windows::fs::MetadataExt,
+    path::PathBuf,
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+    thread,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf},
+    net::{TcpListener, TcpStream},
+    runtime::Handle,
+    sync::mpsc,
+};
+
+const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+const BRIDGE_BUFFER: usize = 64 * 1024;
+
+/// Address the REST server listens on, `host:port`, `[::1]:port` or `unix:/path/to.sock`.
+#[derive(Clone, Debug)]
+pub enum Bind {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl Bind {
+    pub fn loopback(port: u16) -> Self {
+        Self::Tcp(format!("127.0.0.1:{}", port))
+    }
+}
+
+impl Default for Bind {
+    fn default() -> Self {
+        Self::loopback(8070)
+    }
+}
+
+impl FromStr for Bind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("unix socket path is empty".into());
+            }
+            return Ok(Self::Unix(path.into()));
+        }
+
+        match s.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(Self::Tcp(s.into()))
+            }
+            _ => Err(format!(
+                "'{}' is neither 'host:port' nor 'unix:/path/to.sock'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Bind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bind::Tcp(addr) => write!(f, "{}", addr),
+            Bind::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Bind {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub async fn bind(bind: &Bind) -> io::Result<Self> {
+        match bind {
+            Bind::Tcp(addr) => Ok(Self::Tcp(TcpListener::bind(addr).await?)),
+            Bind::Unix(path) => Ok(Self::Unix(UnixListener::bind(path.clone())?)),
+        }
+    }
+}
+
+impl axum::serve::Listener for Listener {
+    type Io = Connection;
+    type Addr = String;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self {
+            Listener::Tcp(l) => {
+                let (io, addr) = axum::serve::Listener::accept(l).await;
+                (Connection::Tcp(io), addr.to_string())
+            }
+            Listener::Unix(l) => (Connection::Unix(l.accept().await), l.addr()),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        match self {
+            Listener::Tcp(l) => Ok(l.local_addr()?.to_string()),
+            Listener::Unix(l) => Ok(l.addr()),
+        }
+    }
+}
+
+/// AF_UNIX listener on top of `uds_windows`, since tokio doesn't provide one on Windows.
+///
+/// Accepted streams are blocking, so each of them is bridged to an in-memory duplex stream
+/// by a pair of threads. The socket file is removed when the listener is dropped.
+pub struct UnixListener {
+    path: PathBuf,
+    incoming: mpsc::Receiver<uds_windows::UnixStream>,
+}
+
+impl UnixListener {
+    fn bind(path: PathBuf) -> io::Result<Self> {
+        if let Ok(meta) = path.metadata() {
+            if uds_windows::UnixStream::connect(&path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("'{}' is already served by another process", path.display()),
+                ));
+            }
+            if meta.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("'{}' exists and is not a socket", path.display()),
+                ));
+            }
+
+            eprintln!("[INFO] removing stale socket '{}'.", path.display());
+            std::fs::remove_file(&path)?;
+        }
+
+        let listener = uds_windows::UnixListener::bind(&path)?;
+        let (tx, incoming) = mpsc::channel(16);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if tx.blocking_send(stream).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => eprintln!("[ERROR] unix socket accept failed: {}", e),
+                }
+            }
+        });
+
+        Ok(Self { path, incoming })
+    }
+
+    async fn accept(&mut self) -> DuplexStream {
+        loop {
+            let Some(stream) = self.incoming.recv().await else {
+                // accept thread is gone, nothing will ever connect again
+                return std::future::pending().await;
+            };
+
+            match bridge(stream) {
+                Ok(io) => return io,
+                Err(e) => eprintln!("[ERROR] cannot bridge unix socket connection: {}", e),
+            }
+        }
+    }
+
+    fn addr(&self) -> String {
+        format!("unix:{}", self.path.display())
+    }
+}
+
+impl Drop for UnixListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn bridge(stream: uds_windows::UnixStream) -> io::Result<DuplexStream> {
+    let (local, remote) = tokio::io::duplex(BRIDGE_BUFFER);
+    let (mut remote_rx, mut remote_tx) = tokio::io::split(remote);
+    let mut stream_rx = stream.try_clone()?;
+    let mut stream_tx = stream;
+    let handle = Handle::current();
+    let rx_handle = handle.clone();
+
+    thread::spawn(move || {
+        let mut buf = vec![0u8; BRIDGE_BUFFER];
+        while let Ok(n) = stream_rx.read(&mut buf) {
+            if n == 0 || rx_handle.block_on(remote_tx.write_all(&buf[..n])).is_err() {
+                break;
+            }
+        }
+        let _ = rx_handle.block_on(remote_tx.shutdown());
+    });
+
+    thread::spawn(move || {
+        let mut buf = vec![0u8; BRIDGE_BUFFER];
+        while let Ok(n) = handle.block_on(remote_rx.read(&mut buf)) {
+            if n == 0 || stream_tx.write_all(&buf[..n]).is_err() {
+                break;
+            }
+        }
+        let _ = stream_tx.shutdown(Shutdown::Both);
+    });
+
+    Ok(local)
+}
+
+pub enum Connection {
+    Tcp(TcpStream),
+    Unix(DuplexStream),
+}
+
+impl Connection {
+    /// Client side of [`Listener`], must be called within a tokio runtime.
+    pub async fn connect(bind: &Bind) -> io::Result<Self> {
+        match bind {
+            Bind::Tcp(addr) => Ok(Self::Tcp(TcpStream::connect(addr).await?)),
+            Bind::Unix(path) => Ok(Self::Unix(bridge(uds_windows::UnixStream::connect(path)?)?)),
+        }
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            Connection::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Connection::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            Connection::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(s) => Pin::new(s).poll_flush(cx),
+            Connection::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            Connection::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
@@ -1,241 +1,299 @@
 use std::{
-    collections::HashMap,
     error::Error,
-    net::SocketAddr,
-    sync::mpsc,
+    process::ExitCode,
+    sync::{Arc, RwLock, mpsc},
     thread,
-    time::{Duration, Instant},
+    time::Duration,
 };
 
-use axum::{
-    Router,
-    extract::Path,
-    http::{StatusCode, Uri},
-    response::Json,
-    routing::{get, post},
-    serve,
-};
-use dll_syringe::{
-    Syringe,
-    process::{OwnedProcess, Process},
-};
-use serde_json::json;
-use tokio::{net::TcpListener, runtime::Builder, signal};
+use tokio::sync::oneshot;
 
 use crate::{
-    remote::{RemoteProcContainer, RemoteProcSignature, ScopedRemoteString},
-    requests::MultiPayload,
+    config::Command,
+    config::Options,
+    invoke::{Invoker, Request, Routes},
+    listener::Bind,
+    server::AppState,
+    targets::Targets,
+    watch::Watch,
 };
 
+mod auth;
+mod call;
+mod client;
 mod config;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod invoke;
+mod launch;
+mod listener;
+mod openapi;
 mod payload;
+mod ps;
 mod remote;
+mod repl;
 mod requests;
-
-fn main() -> Result<(), Box<dyn Error>> {
+mod rpc;
+mod sandbox;
+mod server;
+mod session;
+mod shadow;
+mod split;
+mod stdio;
+mod target;
+mod targets;
+mod tls;
+mod watch;
+mod ws;
+
+fn main() -> Result<ExitCode, Box<dyn Error>> {
     let options = config::Options::load()?;
-    let target_name = options.target_name;
-    let payload_path = options.payload_path;
-    let port = options.port;
-    let paths = options.paths;
-
-    let procedures = payload::analyze_payload(&payload_path, paths)?;
-
-    if let Some(target_process) = OwnedProcess::find_first_by_name(&target_name) {
-        let pid = target_process.pid()?;
-        let base_name = target_process
-            .base_name()?
-            .to_str()
-            .unwrap_or("UNKNOWN BASE NAME")
-            .to_string();
-        let exec_path = target_process
-            .path()?
-            .to_str()
-            .unwrap_or("UNKNOWN EXEC PATH")
-            .to_string();
-
-        println!(
-            "[INFO] injected process base name: {}, path: {}, pid: {}.",
-            base_name, exec_path, pid
-        );
-
-        if options.is_verbose {
-            println!();
-            if let Err(e) = payload::print_symbol_table(&procedures) {
-                eprintln!("[ERROR] failed to print symbols table: {}", e);
-            }
-            println!();
+
+    if options.is_front_end {
+        sandbox::confine().map_err(|e| format!("front end is not confined: {}", e))?;
+    }
+
+    let document = openapi::document(&options.paths);
+    match &options.command {
+        Some(Command::Openapi) => {
+            println!("{}", serde_json::to_string_pretty(&document)?);
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Call(args)) => return call::run(&options, args),
+        Some(Command::Client(args)) => {
+            client::run(&options, args)?;
+            return Ok(ExitCode::SUCCESS);
         }
+        Some(Command::Ps(args)) => {
+            ps::run(&options, args)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => {}
+    }
 
-        let syringe = Syringe::for_process(target_process);
-        let injected_payload = syringe.inject(payload_path)?;
-
-        let procedures: HashMap<_, _> = procedures
-            .into_iter()
-            .filter_map(|(s, m)| {
-                if s != "DllMain"
-                    && m.is_valid()
-                    && let Some(sig) = m.signature
-                {
-                    let procedure = match sig {
-                        RemoteProcSignature::Signal => RemoteProcContainer::Signal(unsafe {
-                            syringe.get_raw_procedure(injected_payload, &s).ok()??
-                        }),
-                        RemoteProcSignature::Text => RemoteProcContainer::Text(unsafe {
-                            syringe.get_raw_procedure(injected_payload, &s).ok()??
-                        }),
-                    };
-
-                    Some((s, procedure))
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        println!(
-            "[INFO] REST procedure call available on http://localhost:{}/",
-            port,
-        );
-
-        type Request = ((String, MultiPayload), mpsc::Sender<Result<String, String>>);
-        let (cmd_tx, cmd_rx) = mpsc::channel::<Request>();
-
-        let info = async move || {
-            Json(json!({
-                "base_name": base_name,
-                "exec_path": exec_path,
-                "pid": pid,
-            }))
+    let bind = options.bind.clone();
+    let tls = options
+        .tls
+        .as_ref()
+        .map(|t| t.server_config())
+        .transpose()?;
+    let tokens = if options.is_serving {
+        Arc::new(auth::Tokens::resolve(&options.auth, &options.paths)?)
+    } else {
+        Default::default()
+    };
+
+    let (cmd_tx, cmd_rx) = mpsc::channel::<Request>();
+    let invoker = Invoker::new(cmd_tx);
+    // kept for the exit watchers of sessions injected later on
+    let watcher = invoker.clone();
+    let (stop_tx, stop_rx) = oneshot::channel();
+
+    let (mut backend, statuses) = if options.is_front_end {
+        let broker = split::Broker::connect(watcher.clone())?;
+        let statuses = vec![(config::DEFAULT_TARGET.into(), broker.status())];
+        (Backend::Broker(broker), statuses)
+    } else {
+        let Some(targets) = Targets::open(&options, &watcher)? else {
+            return Ok(ExitCode::FAILURE);
         };
+        let statuses = targets.statuses();
+        (Backend::Targets(targets), statuses)
+    };
+    let status = statuses[0].1.clone();
+    let paths = Arc::new(RwLock::new(options.paths.clone()));
+    let routes = Arc::new(RwLock::new(Routes::new(&options)));
+    let document = Arc::new(RwLock::new(document));
+
+    let mut server = None;
+    if options.is_serving {
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        match &bind {
+            Bind::Tcp(addr) => {
+                eprintln!(
+                    "[INFO] REST procedure call available on {}://{}/",
+                    scheme, addr
+                )
+            }
+            Bind::Unix(_) => {
+                eprintln!(
+                    "[INFO] REST procedure call available ({}) on {}",
+                    scheme, bind
+                )
+            }
+        }
+        if !tokens.is_enabled() {
+            eprintln!(
+                "[WARNING] authentication is disabled, any local process can call procedures."
+            );
+        }
 
-        let fallback = async |uri: Uri| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(json!({
-                    "message": format!("'{uri}' not found")
-                })),
-            )
+        let state = AppState {
+            invoker: invoker.clone(),
+            status: status.clone(),
+            targets: Arc::new(statuses),
+            paths: paths.clone(),
+            routes: routes.clone(),
+            document: document.clone(),
         };
+        let app = server::router(state, tokens);
+
+        server = Some(thread::spawn(move || server::run(app, bind, tls, stop_rx)));
+    }
+
+    // the session lasts as long as this front end, a server next to the REPL is stopped with it
+    let thandle = if options.is_stdio {
+        eprintln!("[INFO] reading JSON lines requests from stdin.");
+
+        thread::spawn(move || stdio::run(invoker))
+    } else if options.is_repl {
+        let options = options.clone();
+        let status = status.clone();
+        let paths = paths.clone();
+
+        thread::spawn(move || repl::run(invoker, status, paths, options))
+    } else if options.is_split {
+        let status = status.clone();
+        let period = Duration::from_millis(options.timeout);
+
+        thread::spawn(move || split::run_broker(invoker, status, period))
+    } else {
+        drop(invoker);
+        server.take().ok_or("nothing to serve")?
+    };
 
-        let thandle = thread::spawn(move || {
-            let runtime = Builder::new_current_thread().enable_all().build().unwrap();
-
-            runtime.block_on(async {
-                let app = Router::new()
-                    .route("/info", get(info))
-                    .route(
-                        "/execute/{proc}",
-                        post(
-                            |Path(proc): Path<String>, payload: MultiPayload| async move {
-                                let start = Instant::now();
-                                let (reply_tx, reply_rx) = mpsc::channel();
-
-                                cmd_tx.send(((proc, payload), reply_tx)).unwrap();
-
-                                match reply_rx.recv_timeout(Duration::from_millis(500)) {
-                                    Ok(Ok(v)) => (
-                                        StatusCode::OK,
-                                        Json(json!({
-                                            "message": v,
-                                            "elapsed_ms": start.elapsed().as_millis(),
-                                        })),
-                                    ),
-                                    Ok(Err(e)) => (
-                                        StatusCode::INTERNAL_SERVER_ERROR,
-                                        Json(json!({ "error": e })),
-                                    ),
-                                    Err(r) => (
-                                        StatusCode::INTERNAL_SERVER_ERROR,
-                                        Json(json!({ "error": r.to_string() })),
-                                    ),
-                                }
-                            },
-                        ),
-                    )
-                    .fallback(fallback);
-
-                let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
-                let listener = TcpListener::bind(addr).await.unwrap();
-
-                serve(listener, app)
-                    .with_graceful_shutdown(shutdown_signal())
-                    .await
-                    .unwrap();
-            });
-        });
-
-        loop {
-            match cmd_rx.recv_timeout(Duration::from_millis(options.timeout)) {
-                Ok(((path, MultiPayload::Signal), reply_tx)) => {
-                    if let Some(RemoteProcContainer::Signal(proc)) = procedures.get(&path) {
-                        if let Err(e) = proc.call() {
-                            reply_tx.send(Err(e.to_string()))?;
-                        } else {
-                            reply_tx.send(Ok("SACK".into()))?;
-                        }
-                    } else {
-                        reply_tx.send(Err("Invalid payload".into()))?;
-                    }
+    let mut code = ExitCode::SUCCESS;
+    // set when the session ends on its own rather than through its front end
+    let mut ended = false;
+    let mut config = Watch::new(vec![options.config_path.clone()]);
+
+    loop {
+        match cmd_rx.recv_timeout(Duration::from_millis(options.timeout)) {
+            Ok(Request::Exited { pid, code }) => {
+                if backend.exited(pid, code) {
+                    ended = true;
+                    break;
                 }
-                Ok(((path, MultiPayload::Text(text)), reply_tx)) => {
-                    if let Some(RemoteProcContainer::Text(proc)) = procedures.get(&path) {
-                        let outgoing_msg = ScopedRemoteString::new(pid.into(), &text.message)?;
-
-                        match proc.call(outgoing_msg.get_addr()) {
-                            Ok(res) => {
-                                let res = ScopedRemoteString::from_remote(pid.into(), res)?;
-
-                                let s = res.read_remote()?;
-
-                                reply_tx.send(Ok(format!("TACK. {}.", s)))?;
-                            }
-                            Err(e) => reply_tx.send(Err(e.to_string()))?,
-                        }
-                    } else {
-                        reply_tx.send(Err("Invalid payload".into()))?;
-                    }
+            }
+            Ok(request) => {
+                if let Err(e) = backend.handle(request) {
+                    eprintln!("[ERROR] lost the broker: {}", e);
+                    break;
                 }
-
-                Err(mpsc::RecvTimeoutError::Timeout) => {
-                    if thandle.is_finished() {
-                        break;
-                    }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if thandle.is_finished() {
+                    break;
                 }
-                Err(mpsc::RecvTimeoutError::Disconnected) => break,
             }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
         }
 
-        if let Err(e) = thandle.join() {
-            Err(format!("[WARNING] axum thread closed with panic: {:#?}", e))?;
-        } else {
-            println!("[INFO] all good, ejecting payload...");
+        if let Err(e) = backend.tick(&watcher) {
+            eprintln!("[ERROR] {}", e);
+            code = ExitCode::FAILURE;
+            ended = true;
+            break;
         }
 
-        syringe.eject(injected_payload)?;
+        if config.changed(Duration::from_millis(options.timeout)) {
+            reconfigure(&options, &mut backend, &paths, &routes, &document);
+            config.rebase();
+        }
+    }
 
-        println!("[INFO] bye.")
-    } else {
-        eprintln!(
-            "[ERROR] program whose name contains '{}' doesn't seem to be run...",
-            target_name
-        );
+    drop(stop_tx);
+
+    // a front end blocked on its input is left behind, the server stops with `stop_tx`
+    let waits_on_input = options.is_stdio || options.is_repl || options.is_split;
+    let front_end = (!ended || !waits_on_input).then_some(thandle);
+    for handle in server.into_iter().chain(front_end) {
+        if let Err(e) = handle.join() {
+            Err(format!(
+                "[WARNING] front end thread closed with panic: {:#?}",
+                e
+            ))?;
+        }
     }
 
-    Ok(())
+    backend.close()?;
+
+    eprintln!("[INFO] bye.");
+
+    Ok(code)
 }
 
-async fn shutdown_signal() {
-    let ctrl_c = async {
-        signal::ctrl_c()
-            .await
-            .expect("failed to set ctrl+c handler");
-    };
+/// Applies the paths of a changed configuration file, the one in use is kept when the new
+/// one is invalid. Other settings take effect on the next start.
+fn reconfigure(
+    options: &Options,
+    backend: &mut Backend,
+    paths: &RwLock<Vec<config::Identifier>>,
+    routes: &RwLock<Routes>,
+    document: &RwLock<serde_json::Value>,
+) {
+    let res = options.reload().and_then(|new| {
+        backend.reconfigure(&new)?;
+        Ok(new)
+    });
+
+    match res {
+        Ok(new) => {
+            *document.write().unwrap() = openapi::document(&new.paths);
+            *routes.write().unwrap() = Routes::new(&new);
+            *paths.write().unwrap() = new.paths;
+            eprintln!("[INFO] configuration reloaded.");
+        }
+        Err(e) => eprintln!(
+            "[ERROR] configuration not reloaded, keeping the previous one: {}",
+            e
+        ),
+    }
+}
+
+/// Answers the invocation channel, the broker holds the sessions in split mode.
+enum Backend {
+    Targets(Targets),
+    Broker(split::Broker),
+}
+
+impl Backend {
+    fn handle(&mut self, request: Request) -> Result<(), Box<dyn Error>> {
+        match self {
+            Backend::Targets(targets) => targets.handle(request),
+            Backend::Broker(broker) => broker.handle(request)?,
+        }
+
+        Ok(())
+    }
+
+    /// `true` to shut down.
+    fn exited(&mut self, pid: u32, code: Option<u32>) -> bool {
+        match self {
+            Backend::Targets(targets) => targets.exited(pid, code),
+            Backend::Broker(_) => false,
+        }
+    }
 
-    let terminate = std::future::pending::<()>();
+    fn tick(&mut self, watcher: &Invoker) -> Result<(), Box<dyn Error>> {
+        match self {
+            Backend::Targets(targets) => targets.tick(watcher),
+            Backend::Broker(broker) => broker.tick(),
+        }
+    }
 
-    tokio::select! {
-        _ = ctrl_c => {},
-        _ = terminate => {},
+    /// The broker reloads the configuration for its own sessions.
+    fn reconfigure(&mut self, options: &Options) -> Result<(), Box<dyn Error>> {
+        match self {
+            Backend::Targets(targets) => targets.reconfigure(options),
+            Backend::Broker(_) => Ok(()),
+        }
+    }
+
+    fn close(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Backend::Targets(targets) => targets.close(),
+            Backend::Broker(_) => Ok(()),
+        }
     }
 }
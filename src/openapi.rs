@@ -0,0 +1,495 @@
+use serde_json::{Map, Value, json};
+
+use crate::{config::Identifier, remote::RemoteProcSignature};
+
+pub fn document(paths: &[Identifier]) -> Value {
+    let mut routes = Map::new();
+
+    routes.insert(
+        "/info".into(),
+        json!({
+            "get": {
+                "operationId": "info",
+                "summary": "Info of the injected process",
+                "responses": {
+                    "200": {
+                        "description": "Session state, with the injected process once attached",
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/Info" }
+                            }
+                        }
+                    },
+                    "401": { "$ref": "#/components/responses/Unauthorized" },
+                }
+            }
+        }),
+    );
+
+    routes.insert(
+        "/procedures".into(),
+        json!({
+            "get": {
+                "operationId": "procedures",
+                "summary": "Configured paths and their signatures",
+                "responses": {
+                    "200": {
+                        "description": "Configured paths",
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "array",
+                                    "items": { "$ref": "#/components/schemas/Procedure" }
+                                }
+                            }
+                        }
+                    },
+                    "401": { "$ref": "#/components/responses/Unauthorized" },
+                }
+            }
+        }),
+    );
+
+    routes.insert(
+        "/targets".into(),
+        json!({
+            "get": {
+                "operationId": "targets",
+                "summary": "Every configured target with its session state",
+                "responses": {
+                    "200": {
+                        "description": "Targets in configuration order",
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "array",
+                                    "items": { "$ref": "#/components/schemas/Target" }
+                                }
+                            }
+                        }
+                    },
+                    "401": { "$ref": "#/components/responses/Unauthorized" },
+                }
+            }
+        }),
+    );
+
+    routes.insert(
+        "/targets/{id}/info".into(),
+        json!({
+            "get": {
+                "operationId": "target_info",
+                "summary": "Info of the process injected for a target",
+                "parameters": [{ "$ref": "#/components/parameters/TargetId" }],
+                "responses": {
+                    "200": {
+                        "description": "Session state, with the injected process once attached",
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/Info" }
+                            }
+                        }
+                    },
+                    "401": { "$ref": "#/components/responses/Unauthorized" },
+                    "404": { "$ref": "#/components/responses/NotFound" },
+                }
+            }
+        }),
+    );
+
+    for path in paths {
+        routes.insert(format!("/execute/{}", path.name), execute_operation(path));
+        routes.insert(
+            format!("/targets/{{id}}/execute/{}", path.name),
+            target_execute_operation(path),
+        );
+        routes.insert(
+            format!("/broadcast/{}", path.name),
+            broadcast_operation(path),
+        );
+    }
+
+    routes.insert(
+        "/batch".into(),
+        json!({
+            "post": {
+                "operationId": "batch",
+                "summary": "Call procedures in order on the invocation thread",
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/BatchRequest" }
+                        }
+                    }
+                },
+                "responses": {
+                    "200": {
+                        "description": "Results of executed items, in order",
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/BatchResponse" }
+                            }
+                        }
+                    },
+                    "400": { "$ref": "#/components/responses/BadRequest" },
+                    "401": { "$ref": "#/components/responses/Unauthorized" },
+                    "403": { "$ref": "#/components/responses/Forbidden" },
+                    "500": { "$ref": "#/components/responses/Error" },
+                    "503": { "$ref": "#/components/responses/Unavailable" },
+                }
+            }
+        }),
+    );
+
+    routes.insert(
+        "/ws".into(),
+        json!({
+            "get": {
+                "operationId": "ws",
+                "summary": "WebSocket channel, JSON frames {id, proc, args} answered with {id, result|error, elapsed_ms}",
+                "responses": {
+                    "101": { "description": "Switching protocols" },
+                    "401": { "$ref": "#/components/responses/Unauthorized" },
+                }
+            }
+        }),
+    );
+
+    routes.insert(
+        "/rpc".into(),
+        json!({
+            "post": {
+                "operationId": "rpc",
+                "summary": "JSON-RPC 2.0 endpoint, methods are path names",
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": { "type": "object" }
+                        }
+                    }
+                },
+                "responses": {
+                    "200": {
+                        "description": "JSON-RPC response or batch of responses",
+                        "content": {
+                            "application/json": {
+                                "schema": { "type": "object" }
+                            }
+                        }
+                    },
+                    "204": { "description": "Only notifications were sent" },
+                    "401": { "$ref": "#/components/responses/Unauthorized" },
+                }
+            }
+        }),
+    );
+
+    let names: Vec<&str> = paths.iter().map(|p| p.name.as_str()).collect();
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "epiphyte",
+            "version": env!("EPIPHYTE_BUILD_VERSION"),
+        },
+        "paths": routes,
+        "security": [{ "bearer": [] }, {}],
+        "components": {
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" }
+            },
+            "schemas": {
+                "Info": {
+                    "type": "object",
+                    "required": ["state", "restarts"],
+                    "properties": {
+                        "state": { "type": "string", "enum": ["waiting", "attached", "detached"] },
+                        "restarts": { "type": "integer", "format": "int64", "minimum": 0 },
+                        "exit_code": { "type": "integer", "format": "int64", "minimum": 0 },
+                        "reloading": { "type": "boolean", "default": false },
+                        "events": {
+                            "type": "array",
+                            "items": { "$ref": "#/components/schemas/Event" }
+                        },
+                        "base_name": { "type": "string" },
+                        "exec_path": { "type": "string" },
+                        "pid": { "type": "integer", "format": "int64", "minimum": 0 },
+                    }
+                },
+                "Event": {
+                    "type": "object",
+                    "required": ["seq", "kind", "message"],
+                    "properties": {
+                        "seq": { "type": "integer", "format": "int64", "minimum": 1 },
+                        "kind": {
+                            "type": "string",
+                            "enum": ["exited", "waiting", "failed", "recovered"]
+                        },
+                        "message": { "type": "string" },
+                    }
+                },
+                "Target": {
+                    "allOf": [
+                        { "$ref": "#/components/schemas/Info" },
+                        {
+                            "type": "object",
+                            "required": ["id"],
+                            "properties": {
+                                "id": { "type": "string" },
+                            }
+                        }
+                    ]
+                },
+                "BroadcastResponse": {
+                    "type": "object",
+                    "required": ["results", "elapsed_ms"],
+                    "properties": {
+                        "results": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "required": ["target"],
+                                "properties": {
+                                    "target": { "type": "string" },
+                                    "message": { "type": "string" },
+                                    "error": { "type": "string" },
+                                }
+                            }
+                        },
+                        "elapsed_ms": { "type": "integer", "format": "int64" },
+                    }
+                },
+                "Procedure": {
+                    "type": "object",
+                    "required": ["name", "symbol", "signature"],
+                    "properties": {
+                        "name": { "type": "string" },
+                        "symbol": { "type": "string" },
+                        "signature": { "type": "string", "enum": ["signal", "text"] },
+                    }
+                },
+                "Text": {
+                    "type": "object",
+                    "required": ["message"],
+                    "properties": {
+                        "message": { "type": "string" },
+                    }
+                },
+                "Execution": {
+                    "type": "object",
+                    "required": ["message", "elapsed_ms"],
+                    "properties": {
+                        "message": { "type": "string" },
+                        "elapsed_ms": { "type": "integer", "format": "int64" },
+                    }
+                },
+                "BatchRequest": {
+                    "type": "object",
+                    "required": ["items"],
+                    "properties": {
+                        "items": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "required": ["proc"],
+                                "properties": {
+                                    "proc": { "type": "string", "enum": names },
+                                    "body": { "$ref": "#/components/schemas/Text" },
+                                }
+                            }
+                        },
+                        "stop_on_error": { "type": "boolean", "default": false },
+                    }
+                },
+                "BatchResponse": {
+                    "type": "object",
+                    "required": ["results", "elapsed_ms"],
+                    "properties": {
+                        "results": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "required": ["proc", "elapsed_ms"],
+                                "properties": {
+                                    "proc": { "type": "string" },
+                                    "message": { "type": "string" },
+                                    "error": { "type": "string" },
+                                    "elapsed_ms": { "type": "integer", "format": "int64" },
+                                }
+                            }
+                        },
+                        "elapsed_ms": { "type": "integer", "format": "int64" },
+                    }
+                },
+                "Error": {
+                    "type": "object",
+                    "required": ["error"],
+                    "properties": {
+                        "error": { "type": "string" },
+                    }
+                },
+            },
+            "parameters": {
+                "TargetId": {
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                }
+            },
+            "responses": {
+                "Unauthorized": {
+                    "description": "Missing or invalid bearer token",
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/Error" }
+                        }
+                    }
+                },
+                "Forbidden": {
+                    "description": "Token is not allowed to execute this path",
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/Error" }
+                        }
+                    }
+                },
+                "BadRequest": {
+                    "description": "Invalid or unreadable body",
+                    "content": {
+                        "text/plain": {
+                            "schema": { "type": "string" }
+                        }
+                    }
+                },
+                "Error": {
+                    "description": "Procedure call failed",
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/Error" }
+                        }
+                    }
+                },
+                "NotFound": {
+                    "description": "No target with this id",
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/Error" }
+                        }
+                    }
+                },
+                "Unavailable": {
+                    "description": "Target is not attached",
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/Error" }
+                        }
+                    }
+                },
+            }
+        }
+    })
+}
+
+fn execute_operation(path: &Identifier) -> Value {
+    let operation = json!({
+        "operationId": operation_id(path),
+        "summary": format!("Call '{}' on the injected payload", path.symbol),
+        "responses": {
+            "200": {
+                "description": "Procedure called",
+                "content": {
+                    "application/json": {
+                        "schema": { "$ref": "#/components/schemas/Execution" }
+                    }
+                }
+            },
+            "400": { "$ref": "#/components/responses/BadRequest" },
+            "401": { "$ref": "#/components/responses/Unauthorized" },
+            "403": { "$ref": "#/components/responses/Forbidden" },
+            "500": { "$ref": "#/components/responses/Error" },
+            "503": { "$ref": "#/components/responses/Unavailable" },
+        }
+    });
+
+    post(operation, path)
+}
+
+fn target_execute_operation(path: &Identifier) -> Value {
+    let operation = json!({
+        "operationId": format!("{}_on_target", operation_id(path)),
+        "summary": format!("Call '{}' on the payload injected for a target", path.symbol),
+        "parameters": [{ "$ref": "#/components/parameters/TargetId" }],
+        "responses": {
+            "200": {
+                "description": "Procedure called",
+                "content": {
+                    "application/json": {
+                        "schema": { "$ref": "#/components/schemas/Execution" }
+                    }
+                }
+            },
+            "400": { "$ref": "#/components/responses/BadRequest" },
+            "401": { "$ref": "#/components/responses/Unauthorized" },
+            "403": { "$ref": "#/components/responses/Forbidden" },
+            "404": { "$ref": "#/components/responses/NotFound" },
+            "500": { "$ref": "#/components/responses/Error" },
+            "503": { "$ref": "#/components/responses/Unavailable" },
+        }
+    });
+
+    post(operation, path)
+}
+
+fn broadcast_operation(path: &Identifier) -> Value {
+    let operation = json!({
+        "operationId": format!("{}_on_all", operation_id(path)),
+        "summary": format!("Call '{}' on every target in turn", path.symbol),
+        "responses": {
+            "200": {
+                "description": "Result of every target, in configuration order",
+                "content": {
+                    "application/json": {
+                        "schema": { "$ref": "#/components/schemas/BroadcastResponse" }
+                    }
+                }
+            },
+            "400": { "$ref": "#/components/responses/BadRequest" },
+            "401": { "$ref": "#/components/responses/Unauthorized" },
+            "403": { "$ref": "#/components/responses/Forbidden" },
+            "500": { "$ref": "#/components/responses/Error" },
+        }
+    });
+
+    post(operation, path)
+}
+
+/// Path names of `[[payloads]]` hold a slash, which operation ids don't allow.
+fn operation_id(path: &Identifier) -> String {
+    path.name.replace('/', "_")
+}
+
+/// Wraps `operation` as a POST, with the request body of the path's signature.
+fn post(mut operation: Value, path: &Identifier) -> Value {
+    if let Some(schema) = request_schema(path.signature) {
+        operation["requestBody"] = json!({
+            "required": true,
+            "content": {
+                "application/json": {
+                    "schema": schema
+                }
+            }
+        });
+    }
+
+    json!({ "post": operation })
+}
+
+fn request_schema(signature: RemoteProcSignature) -> Option<Value> {
+    match signature {
+        RemoteProcSignature::Signal => None,
+        RemoteProcSignature::Text => Some(json!({ "$ref": "#/components/schemas/Text" })),
+    }
+}
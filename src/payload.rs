@@ -1,6 +1,6 @@
 use std::{collections::HashMap, path::PathBuf};
 
-use cli_table::{Cell, Style, Table, print_stdout};
+use cli_table::{Cell, Style, Table, print_stderr};
 use object::{File, Object};
 
 use crate::{config::Identifier, remote::RemoteProcSignature};
@@ -88,6 +88,6 @@ pub fn print_symbol_table(symbol: &HashMap<String, Metadata>) -> Result<(), std:
         ])
         .bold(true);
 
-    println!("[INFO] Symbol Table");
-    print_stdout(t)
+    eprintln!("[INFO] Symbol Table");
+    print_stderr(t)
 }
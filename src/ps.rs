@@ -0,0 +1,114 @@
+use std::{error::Error, path::PathBuf};
+
+use cli_table::{Cell, Style, Table, print_stdout};
+use dll_syringe::process::{OwnedProcess, Process};
+use serde::Serialize;
+
+use crate::{
+    config::{Options, Output, PsArgs},
+    shadow,
+    target::{self, Candidate, Selector},
+};
+
+#[derive(Serialize)]
+struct Row {
+    pid: u32,
+    name: String,
+    exe: Option<String>,
+    /// `None` when the process cannot be opened
+    arch: Option<&'static str>,
+    /// `None` when the process cannot be opened or no payload is configured
+    payload_loaded: Option<bool>,
+}
+
+/// Lists processes through the same enumeration as target selection.
+pub fn run(options: &Options, args: &PsArgs) -> Result<(), Box<dyn Error>> {
+    let selector = Selector {
+        name_contains: args.filter.clone(),
+        ..Default::default()
+    };
+    // the payload counts as loaded through its shadow copy of the build on disk too
+    let payloads = options
+        .payloads
+        .iter()
+        .map(|p| {
+            let mut paths = vec![std::path::absolute(&p.path)?];
+            paths.extend(shadow::current(&p.path).ok());
+            Ok(paths)
+        })
+        .collect::<Result<Vec<_>, std::io::Error>>()?;
+
+    let rows: Vec<Row> = selector
+        .filter(target::processes())?
+        .into_iter()
+        .map(|c| row(c, &payloads))
+        .collect();
+
+    match args.output {
+        Output::Text => {
+            let unknown = || "?".to_string();
+            let t = rows
+                .into_iter()
+                .map(|r| {
+                    vec![
+                        r.pid.cell(),
+                        r.name.cell(),
+                        r.exe.unwrap_or_default().cell(),
+                        r.arch.map_or_else(unknown, String::from).cell(),
+                        r.payload_loaded
+                            .map_or_else(unknown, |v| if v { "yes" } else { "no" }.into())
+                            .cell(),
+                    ]
+                })
+                .table()
+                .title(vec![
+                    "PID".cell().bold(true),
+                    "Name".cell().bold(true),
+                    "Path".cell().bold(true),
+                    "Arch".cell().bold(true),
+                    "Payload".cell().bold(true),
+                ])
+                .bold(true);
+
+            print_stdout(t)?;
+        }
+        Output::Json => println!("{}", serde_json::to_string_pretty(&rows)?),
+    }
+
+    Ok(())
+}
+
+/// `payload_loaded` is set when every payload is loaded.
+fn row(candidate: Candidate, payloads: &[Vec<PathBuf>]) -> Row {
+    // processes of other users or higher integrity can't be opened
+    let process = OwnedProcess::from_pid(candidate.pid).ok();
+
+    let arch = process
+        .as_ref()
+        .and_then(|p| p.is_x64().ok())
+        .map(|x64| if x64 { "x64" } else { "x86" });
+    let payload_loaded = process
+        .as_ref()
+        .filter(|_| !payloads.is_empty())
+        .and_then(|p| {
+            payloads
+                .iter()
+                .map(|paths| {
+                    paths
+                        .iter()
+                        .map(|path| p.find_module_by_path(path).ok().map(|m| m.is_some()))
+                        .collect::<Option<Vec<bool>>>()
+                        .map(|loaded| loaded.contains(&true))
+                })
+                .collect::<Option<Vec<bool>>>()
+        })
+        .map(|loaded| loaded.into_iter().all(|v| v));
+
+    Row {
+        pid: candidate.pid,
+        name: candidate.name,
+        exe: candidate.exe.map(|v| v.display().to_string()),
+        arch,
+        payload_loaded,
+    }
+}
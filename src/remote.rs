@@ -3,7 +3,7 @@ use std::ffi::{CString, c_void};
 use std::mem::MaybeUninit;
 
 use dll_syringe::rpc::RemoteRawProcedure as Proc;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use windows::Win32::Foundation::{CloseHandle, HANDLE};
 use windows::Win32::System::Diagnostics::Debug::ReadProcessMemory;
 use windows::Win32::System::Memory::{MEMORY_BASIC_INFORMATION, VirtualQueryEx};
@@ -21,7 +21,7 @@ pub enum RemoteProcContainer {
     Text(Proc<extern "system" fn(usize) -> usize>),
 }
 
-#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum RemoteProcSignature {
     #[default]
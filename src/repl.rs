@@ -0,0 +1,189 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use rustyline::{
+    Context, Editor, Helper, Highlighter, Hinter, Validator, completion::Completer,
+    error::ReadlineError, history::DefaultHistory,
+};
+
+use crate::{
+    config::{Identifier, Options},
+    invoke::{Invoker, Reply},
+    payload,
+    remote::RemoteProcSignature,
+    requests::{MultiPayload, Text},
+    server::Status,
+};
+
+const COMMANDS: [&str; 7] = [
+    "call", ":info", ":symbols", ":reload", ":eject", ":help", ":quit",
+];
+
+const HELP: &str = "\
+call <path> [text]  call a path, text is taken as is or as a JSON string when quoted
+:info               session state and injected process
+:symbols            symbol table of the payload
+:reload             eject and inject the payload again
+:eject              eject the payload, calls fail until :reload
+:quit               eject and exit, same as ctrl+d";
+
+/// Completes commands, and path names after `call`.
+#[derive(Helper, Highlighter, Hinter, Validator)]
+struct Completion {
+    paths: Arc<RwLock<Vec<Identifier>>>,
+}
+
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+
+        let paths = self.paths.read().unwrap();
+        let (start, candidates): (usize, Vec<&str>) = match line.strip_prefix("call ") {
+            Some(rest) if !rest.trim_start().contains(' ') => (
+                pos - rest.trim_start().len(),
+                paths.iter().map(|p| p.name.as_str()).collect(),
+            ),
+            None if !line.contains(' ') => (0, COMMANDS.to_vec()),
+            _ => return Ok((pos, Vec::new())),
+        };
+
+        let word = &line[start..];
+        let candidates = candidates
+            .into_iter()
+            .filter(|c| c.starts_with(word))
+            .map(String::from)
+            .collect();
+
+        Ok((start, candidates))
+    }
+}
+
+/// Reads commands from the terminal until `:quit` or EOF.
+pub fn run(
+    invoker: Invoker,
+    status: Arc<RwLock<Status>>,
+    paths: Arc<RwLock<Vec<Identifier>>>,
+    options: Options,
+) {
+    let mut editor = match Editor::<Completion, DefaultHistory>::new() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("[ERROR] cannot start REPL: {}", e);
+            return;
+        }
+    };
+    editor.set_helper(Some(Completion {
+        paths: paths.clone(),
+    }));
+
+    eprintln!("[INFO] REPL is ready, :help lists commands.");
+
+    loop {
+        let line = match editor.readline("epiphyte> ") {
+            Ok(v) => v,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("[ERROR] cannot read line: {}", e);
+                break;
+            }
+        };
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+
+        let (command, args) = split(line);
+        match (command, args) {
+            ("call", args) => {
+                // not held during the call, the invocation thread may be replacing it
+                let paths = paths.read().unwrap().clone();
+                call(&invoker, &paths, args)
+            }
+            (":info", "") => match serde_json::to_string_pretty(&*status.read().unwrap()) {
+                Ok(v) => println!("{}", v),
+                Err(e) => eprintln!("[ERROR] {}", e),
+            },
+            (":symbols", "") => symbols(&options),
+            (":reload", "") => print(invoker.reload_blocking()),
+            (":eject", "") => print(invoker.eject_blocking()),
+            (":help", "") => println!("{}", HELP),
+            (":quit", "") => break,
+            _ => eprintln!("[ERROR] unknown command '{}', see :help", line),
+        }
+    }
+}
+
+fn split(s: &str) -> (&str, &str) {
+    s.split_once(char::is_whitespace)
+        .map_or((s, ""), |(head, rest)| (head, rest.trim()))
+}
+
+fn call(invoker: &Invoker, paths: &[Identifier], args: &str) {
+    let (name, arg) = split(args);
+    let Some(path) = paths.iter().find(|p| p.name == name) else {
+        eprintln!("[ERROR] '{}' is not a path", name);
+        return;
+    };
+
+    let payload = match (path.signature, arg) {
+        (RemoteProcSignature::Signal, "") => MultiPayload::Signal,
+        (RemoteProcSignature::Signal, _) => {
+            eprintln!("[ERROR] '{}' is a signal path and takes no text", name);
+            return;
+        }
+        (RemoteProcSignature::Text, "") => {
+            eprintln!("[ERROR] '{}' takes text, e.g. call {} \"text\"", name, name);
+            return;
+        }
+        // quoted text follows JSON string escapes
+        (RemoteProcSignature::Text, arg) if arg.starts_with('"') => {
+            match serde_json::from_str(arg) {
+                Ok(message) => MultiPayload::Text(Text { message }),
+                Err(e) => {
+                    eprintln!("[ERROR] invalid quoted text: {}", e);
+                    return;
+                }
+            }
+        }
+        (RemoteProcSignature::Text, arg) => MultiPayload::Text(Text {
+            message: arg.into(),
+        }),
+    };
+
+    let start = Instant::now();
+    let res = invoker.call_blocking(name.into(), payload);
+    print(res.map(|v| format!("{} ({} ms)", v, start.elapsed().as_millis())));
+}
+
+fn symbols(options: &Options) {
+    let res = options.payloads().and_then(|payloads| {
+        for p in payloads {
+            let metadata = payload::analyze_payload(&p.path, p.paths.clone())?;
+            payload::print_symbol_table(&metadata)?;
+        }
+        Ok(())
+    });
+
+    if let Err(e) = res {
+        eprintln!("[ERROR] failed to print symbols table: {}", e);
+    }
+}
+
+fn print(reply: Reply) {
+    match reply {
+        Ok(v) => println!("{}", v),
+        Err(e) => eprintln!("[ERROR] {}", e),
+    }
+}
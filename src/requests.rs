@@ -5,19 +5,41 @@ use axum::{
     response::IntoResponse,
 };
 use serde::Deserialize;
+use serde_json::Value;
 
-#[derive(Debug, Deserialize)]
+const UNKNOWN_FORMAT: &str = "Request payload didn't match any known format";
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct Text {
     pub message: String,
 }
+#[derive(Clone)]
 pub enum MultiPayload {
     Text(Text),
     Signal,
 }
 
+impl MultiPayload {
+    /// Payload embedded in a JSON document, where a missing or null body is a signal.
+    pub fn from_value(value: Option<Value>) -> Result<Self, MultiPayloadRejection> {
+        match value {
+            None | Some(Value::Null) => Ok(Self::Signal),
+            Some(v) => serde_json::from_value::<Text>(v)
+                .map(Self::Text)
+                .map_err(|_| MultiPayloadRejection::from(UNKNOWN_FORMAT)),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MultiPayloadRejection(String);
 
+impl MultiPayloadRejection {
+    pub fn message(&self) -> &str {
+        &self.0
+    }
+}
+
 impl<S> From<S> for MultiPayloadRejection
 where
     S: ToString,
@@ -56,8 +78,6 @@ where
             return Ok(Self::Text(v));
         }
 
-        Err(MultiPayloadRejection::from(
-            "Request payload didn't match any known format",
-        ))
+        Err(MultiPayloadRejection::from(UNKNOWN_FORMAT))
     }
 }
@@ -0,0 +1,178 @@
+use axum::{
+    body::Bytes,
+    extract::{Extension, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::{Map, Value, json};
+
+use crate::{auth::Scope, remote::RemoteProcSignature, requests::MultiPayload, server::AppState};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// procedure was called but reported an error
+const CALL_FAILED: i64 = -32000;
+/// token scope doesn't include the method
+const FORBIDDEN: i64 = -32001;
+/// no target attached
+const UNAVAILABLE: i64 = -32002;
+
+/// JSON-RPC 2.0 over `POST /rpc`, method names are path names.
+pub async fn handler(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    body: Bytes,
+) -> Response {
+    let request = match serde_json::from_slice::<Value>(&body) {
+        Ok(v) => v,
+        Err(e) => return Json(error(Value::Null, PARSE_ERROR, e.to_string())).into_response(),
+    };
+
+    let reply = match request {
+        Value::Array(batch) if batch.is_empty() => {
+            Some(error(Value::Null, INVALID_REQUEST, "empty batch".into()))
+        }
+        Value::Array(batch) => {
+            let mut replies = Vec::with_capacity(batch.len());
+            for request in batch {
+                if let Some(reply) = single(&state, &scope, request).await {
+                    replies.push(reply);
+                }
+            }
+            (!replies.is_empty()).then_some(Value::Array(replies))
+        }
+        request => single(&state, &scope, request).await,
+    };
+
+    match reply {
+        Some(v) => Json(v).into_response(),
+        // nothing but notifications
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+/// Handles one request object, returns `None` for notifications.
+async fn single(state: &AppState, scope: &Scope, request: Value) -> Option<Value> {
+    let Value::Object(mut request) = request else {
+        return Some(error(
+            Value::Null,
+            INVALID_REQUEST,
+            "request is not an object".into(),
+        ));
+    };
+
+    let id = request.remove("id");
+    let reply_id = id.clone().unwrap_or(Value::Null);
+
+    if request.get("jsonrpc") != Some(&json!("2.0")) {
+        return Some(error(
+            reply_id,
+            INVALID_REQUEST,
+            "jsonrpc must be \"2.0\"".into(),
+        ));
+    }
+    let Some(Value::String(method)) = request.remove("method") else {
+        return Some(error(
+            reply_id,
+            INVALID_REQUEST,
+            "method must be a string".into(),
+        ));
+    };
+
+    let reply = call(state, scope, method, request.remove("params")).await;
+
+    // notifications are executed but never answered, even on errors
+    id?;
+
+    Some(match reply {
+        Ok(v) => json!({ "jsonrpc": "2.0", "result": v, "id": reply_id }),
+        Err((code, message)) => error(reply_id, code, message),
+    })
+}
+
+async fn call(
+    state: &AppState,
+    scope: &Scope,
+    method: String,
+    params: Option<Value>,
+) -> Result<String, (i64, String)> {
+    let method = state.canonical(&method);
+    let signature = state
+        .paths
+        .read()
+        .unwrap()
+        .iter()
+        .find(|p| p.name == method)
+        .map(|p| p.signature);
+    let Some(signature) = signature else {
+        return Err((METHOD_NOT_FOUND, format!("'{}' is not a path", method)));
+    };
+
+    if !scope.allows(&method) {
+        return Err((
+            FORBIDDEN,
+            format!("token is not allowed to execute '{}'", method),
+        ));
+    }
+
+    if let Some(reason) = state.status.read().unwrap().unavailable() {
+        return Err((UNAVAILABLE, reason.into()));
+    }
+
+    let payload = payload(signature, params).ok_or((
+        INVALID_PARAMS,
+        format!("params don't match '{:?}' signature", signature),
+    ))?;
+
+    state
+        .invoker
+        .call(method, payload)
+        .await
+        .map_err(|e| (CALL_FAILED, e))
+}
+
+/// Maps by-name or by-position params onto the arguments of `signature`.
+fn payload(signature: RemoteProcSignature, params: Option<Value>) -> Option<MultiPayload> {
+    let params = match params {
+        None | Some(Value::Null) => None,
+        Some(Value::Array(v)) if v.is_empty() => None,
+        Some(Value::Object(v)) if v.is_empty() => None,
+        Some(Value::Array(mut v)) if v.len() == 1 => {
+            let mut named = Map::new();
+            named.insert("message".into(), v.remove(0));
+            Some(Value::Object(named))
+        }
+        Some(v @ Value::Object(_)) => Some(v),
+        Some(_) => return None,
+    };
+
+    match (signature, params) {
+        (RemoteProcSignature::Signal, None) => Some(MultiPayload::Signal),
+        (RemoteProcSignature::Text, Some(v)) => match MultiPayload::from_value(Some(v)) {
+            Ok(p @ MultiPayload::Text(_)) => Some(p),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Error response with the standard message for `code` and details in `data`.
+fn error(id: Value, code: i64, data: String) -> Value {
+    let message = match code {
+        PARSE_ERROR => "Parse error",
+        INVALID_REQUEST => "Invalid Request",
+        METHOD_NOT_FOUND => "Method not found",
+        INVALID_PARAMS => "Invalid params",
+        FORBIDDEN => "Forbidden",
+        UNAVAILABLE => "Unavailable",
+        _ => "Server error",
+    };
+
+    json!({
+        "jsonrpc": "2.0",
+        "error": { "code": code, "message": message, "data": data },
+        "id": id,
+    })
+}
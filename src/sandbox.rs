@@ -0,0 +1,415 @@
+//! Confinement of the split mode front end. The broker starts it with a restricted token at
+//! low integrity, so it can't open processes running at a higher level whatever their
+//! security descriptor, in a job object that kills it once the broker is gone.
+
+use std::{
+    error::Error,
+    ffi::{OsStr, c_void},
+    fs::File,
+    iter, mem,
+    os::windows::{
+        ffi::OsStrExt,
+        io::{AsRawHandle, FromRawHandle, OwnedHandle},
+    },
+    path::Path,
+    ptr,
+};
+
+use windows::{
+    Win32::{
+        Foundation::{
+            CloseHandle, DUPLICATE_SAME_ACCESS, DuplicateHandle, HANDLE, HANDLE_FLAG_INHERIT,
+            SetHandleInformation,
+        },
+        Security::{
+            AdjustTokenPrivileges, AllocateAndInitializeSid, CreateRestrictedToken,
+            DISABLE_MAX_PRIVILEGE, FreeSid, GetLengthSid, GetSidSubAuthority,
+            GetSidSubAuthorityCount, GetTokenInformation, PSID, SE_PRIVILEGE_REMOVED,
+            SECURITY_MANDATORY_LABEL_AUTHORITY, SID_AND_ATTRIBUTES, SetTokenInformation,
+            TOKEN_ADJUST_PRIVILEGES, TOKEN_ASSIGN_PRIMARY, TOKEN_DUPLICATE, TOKEN_MANDATORY_LABEL,
+            TOKEN_PRIVILEGES, TOKEN_QUERY, TokenIntegrityLevel, TokenPrivileges,
+        },
+        System::{
+            Console::{GetStdHandle, STD_ERROR_HANDLE},
+            JobObjects::{
+                AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+                JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JobObjectExtendedLimitInformation,
+                SetInformationJobObject,
+            },
+            Pipes::CreatePipe,
+            SystemServices::{SE_GROUP_INTEGRITY, SECURITY_MANDATORY_LOW_RID},
+            Threading::{
+                CREATE_SUSPENDED, CreateProcessAsUserW, DeleteProcThreadAttributeList,
+                EXTENDED_STARTUPINFO_PRESENT, GetCurrentProcess, GetExitCodeProcess, INFINITE,
+                InitializeProcThreadAttributeList, LPPROC_THREAD_ATTRIBUTE_LIST, OpenProcessToken,
+                PROC_THREAD_ATTRIBUTE_HANDLE_LIST, PROCESS_INFORMATION, ResumeThread,
+                STARTF_USESTDHANDLES, STARTUPINFOEXW, STARTUPINFOW, TerminateProcess,
+                UpdateProcThreadAttribute, WaitForSingleObject,
+            },
+        },
+    },
+    core::{PCWSTR, PWSTR},
+};
+
+/// Front end process started by [`spawn`], with the broker's ends of its stdin and stdout.
+pub struct FrontEnd {
+    pub pid: u32,
+    pub stdin: Option<File>,
+    pub stdout: Option<File>,
+    process: OwnedHandle,
+    /// closed along with the broker however it exits, which kills the front end
+    _job: OwnedHandle,
+}
+
+impl FrontEnd {
+    /// Blocks until the front end exits, returning its exit code.
+    pub fn wait(&self) -> windows::core::Result<u32> {
+        unsafe {
+            let process = HANDLE(self.process.as_raw_handle());
+            WaitForSingleObject(process, INFINITE);
+
+            let mut code = 0;
+            GetExitCodeProcess(process, &mut code)?;
+
+            Ok(code)
+        }
+    }
+}
+
+/// Starts `program` confined, with piped stdin and stdout and the broker's stderr. Only
+/// these handles are inherited, an inherited handle to the target would bypass its
+/// integrity level.
+pub fn spawn(
+    program: &Path,
+    args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+) -> Result<FrontEnd, Box<dyn Error>> {
+    let mut line = Vec::new();
+    quote(program.as_os_str(), &mut line);
+    for arg in args {
+        line.push(b' ' as u16);
+        quote(arg.as_ref(), &mut line);
+    }
+    line.push(0);
+    let program = program
+        .as_os_str()
+        .encode_wide()
+        .chain(iter::once(0))
+        .collect::<Vec<_>>();
+
+    let token = restricted_token()?;
+    let (stdin, to_front) = pipe()?;
+    let (from_front, stdout) = pipe()?;
+    let stderr = stderr()?;
+
+    let mut handles = vec![stdin.as_raw_handle(), stdout.as_raw_handle()];
+    handles.extend(stderr.as_ref().map(|v| v.as_raw_handle()));
+    for handle in &handles {
+        unsafe {
+            SetHandleInformation(HANDLE(*handle), HANDLE_FLAG_INHERIT.0, HANDLE_FLAG_INHERIT)?
+        };
+    }
+
+    let process = unsafe {
+        let mut len = 0;
+        // only fails with ERROR_INSUFFICIENT_BUFFER after filling in `len`
+        let _ = InitializeProcThreadAttributeList(None, 1, None, &mut len);
+
+        // usize keeps the buffer aligned for the attribute list
+        let mut buf = vec![0usize; len.div_ceil(mem::size_of::<usize>())];
+        let attributes = LPPROC_THREAD_ATTRIBUTE_LIST(buf.as_mut_ptr() as *mut c_void);
+        InitializeProcThreadAttributeList(Some(attributes), 1, None, &mut len)?;
+
+        let info = STARTUPINFOEXW {
+            StartupInfo: STARTUPINFOW {
+                cb: mem::size_of::<STARTUPINFOEXW>() as u32,
+                dwFlags: STARTF_USESTDHANDLES,
+                hStdInput: HANDLE(stdin.as_raw_handle()),
+                hStdOutput: HANDLE(stdout.as_raw_handle()),
+                hStdError: HANDLE(
+                    stderr
+                        .as_ref()
+                        .map_or(ptr::null_mut(), |v| v.as_raw_handle()),
+                ),
+                ..Default::default()
+            },
+            lpAttributeList: attributes,
+        };
+        let mut process = PROCESS_INFORMATION::default();
+        let res = UpdateProcThreadAttribute(
+            attributes,
+            0,
+            PROC_THREAD_ATTRIBUTE_HANDLE_LIST as usize,
+            Some(handles.as_ptr() as *const c_void),
+            mem::size_of_val(handles.as_slice()),
+            None,
+            None,
+        )
+        .and_then(|_| {
+            // suspended until it's in the job, so it can't spawn anything outside of it
+            CreateProcessAsUserW(
+                Some(HANDLE(token.as_raw_handle())),
+                PCWSTR(program.as_ptr()),
+                Some(PWSTR(line.as_mut_ptr())),
+                None,
+                None,
+                true,
+                CREATE_SUSPENDED | EXTENDED_STARTUPINFO_PRESENT,
+                None,
+                PCWSTR::null(),
+                &info.StartupInfo,
+                &mut process,
+            )
+        });
+        DeleteProcThreadAttributeList(attributes);
+        res?;
+
+        process
+    };
+
+    let thread = unsafe { OwnedHandle::from_raw_handle(process.hThread.0) };
+    let handle = unsafe { OwnedHandle::from_raw_handle(process.hProcess.0) };
+    let job = match job(&handle) {
+        Ok(v) => v,
+        Err(e) => {
+            let _ = unsafe { TerminateProcess(process.hProcess, 1) };
+            Err(format!("cannot put front end in a job: {}", e))?
+        }
+    };
+    unsafe { ResumeThread(HANDLE(thread.as_raw_handle())) };
+
+    Ok(FrontEnd {
+        pid: process.dwProcessId,
+        stdin: Some(File::from(to_front)),
+        stdout: Some(File::from(from_front)),
+        process: handle,
+        _job: job,
+    })
+}
+
+/// Quotes `arg` as `CommandLineToArgvW` splits it.
+fn quote(arg: &OsStr, line: &mut Vec<u16>) {
+    let arg = arg.encode_wide().collect::<Vec<_>>();
+    if !arg.is_empty()
+        && !arg
+            .iter()
+            .any(|&c| c == b' ' as u16 || c == b'\t' as u16 || c == b'"' as u16)
+    {
+        line.extend(arg);
+        return;
+    }
+
+    line.push(b'"' as u16);
+    let mut backslashes = 0;
+    for c in arg {
+        if c == b'\\' as u16 {
+            backslashes += 1;
+        } else {
+            // backslashes are only escapes in front of a quote
+            if c == b'"' as u16 {
+                line.extend(iter::repeat_n(b'\\' as u16, backslashes + 1));
+            }
+            backslashes = 0;
+        }
+        line.push(c);
+    }
+    line.extend(iter::repeat_n(b'\\' as u16, backslashes));
+    line.push(b'"' as u16);
+}
+
+/// Read and write ends of an anonymous pipe, neither inheritable yet.
+fn pipe() -> windows::core::Result<(OwnedHandle, OwnedHandle)> {
+    let (mut read, mut write) = (HANDLE::default(), HANDLE::default());
+    unsafe {
+        CreatePipe(&mut read, &mut write, None, 0)?;
+        Ok((
+            OwnedHandle::from_raw_handle(read.0),
+            OwnedHandle::from_raw_handle(write.0),
+        ))
+    }
+}
+
+/// Copy of the broker's stderr to hand down, `None` when there's none.
+fn stderr() -> windows::core::Result<Option<OwnedHandle>> {
+    unsafe {
+        let stderr = GetStdHandle(STD_ERROR_HANDLE)?;
+        if stderr.is_invalid() {
+            return Ok(None);
+        }
+
+        let mut copy = HANDLE::default();
+        DuplicateHandle(
+            GetCurrentProcess(),
+            stderr,
+            GetCurrentProcess(),
+            &mut copy,
+            0,
+            false,
+            DUPLICATE_SAME_ACCESS,
+        )?;
+
+        Ok(Some(OwnedHandle::from_raw_handle(copy.0)))
+    }
+}
+
+/// Broker's token without any privilege but `SeChangeNotifyPrivilege`, at low integrity.
+fn restricted_token() -> windows::core::Result<OwnedHandle> {
+    unsafe {
+        let mut token = HANDLE::default();
+        OpenProcessToken(
+            GetCurrentProcess(),
+            TOKEN_DUPLICATE | TOKEN_ASSIGN_PRIMARY | TOKEN_QUERY,
+            &mut token,
+        )?;
+        let token = OwnedHandle::from_raw_handle(token.0);
+
+        let mut restricted = HANDLE::default();
+        CreateRestrictedToken(
+            HANDLE(token.as_raw_handle()),
+            DISABLE_MAX_PRIVILEGE,
+            None,
+            None,
+            None,
+            &mut restricted,
+        )?;
+        let restricted = OwnedHandle::from_raw_handle(restricted.0);
+
+        let mut sid = PSID(ptr::null_mut());
+        AllocateAndInitializeSid(
+            &SECURITY_MANDATORY_LABEL_AUTHORITY,
+            1,
+            SECURITY_MANDATORY_LOW_RID as u32,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            &mut sid,
+        )?;
+        let label = TOKEN_MANDATORY_LABEL {
+            Label: SID_AND_ATTRIBUTES {
+                Sid: sid,
+                Attributes: SE_GROUP_INTEGRITY as u32,
+            },
+        };
+        let res = SetTokenInformation(
+            HANDLE(restricted.as_raw_handle()),
+            TokenIntegrityLevel,
+            &label as *const _ as *const c_void,
+            mem::size_of::<TOKEN_MANDATORY_LABEL>() as u32 + GetLengthSid(sid),
+        );
+        FreeSid(sid);
+        res?;
+
+        Ok(restricted)
+    }
+}
+
+/// Job killing `process` once its handle is closed.
+fn job(process: &OwnedHandle) -> windows::core::Result<OwnedHandle> {
+    unsafe {
+        let job = OwnedHandle::from_raw_handle(CreateJobObjectW(None, PCWSTR::null())?.0);
+
+        let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        SetInformationJobObject(
+            HANDLE(job.as_raw_handle()),
+            JobObjectExtendedLimitInformation,
+            &limits as *const _ as *const c_void,
+            mem::size_of_val(&limits) as u32,
+        )?;
+        AssignProcessToJobObject(HANDLE(job.as_raw_handle()), HANDLE(process.as_raw_handle()))?;
+
+        Ok(job)
+    }
+}
+
+/// Front end side: checks it was started confined, then removes what's left of its
+/// privileges for good. Errors mean it must not serve anything.
+pub fn confine() -> Result<(), Box<dyn Error>> {
+    let level = integrity_level()?;
+    if level > SECURITY_MANDATORY_LOW_RID as u32 {
+        Err(format!(
+            "front end runs at integrity level {:#x}, it must be started by the broker",
+            level
+        ))?;
+    }
+
+    Ok(drop_privileges()?)
+}
+
+/// Mandatory label RID of this process, e.g. `SECURITY_MANDATORY_LOW_RID`.
+fn integrity_level() -> windows::core::Result<u32> {
+    unsafe {
+        let mut token = HANDLE::default();
+        OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token)?;
+
+        let mut len = 0;
+        // only fails with ERROR_INSUFFICIENT_BUFFER after filling in `len`
+        let _ = GetTokenInformation(token, TokenIntegrityLevel, None, 0, &mut len);
+
+        // u32 keeps the buffer aligned for TOKEN_MANDATORY_LABEL
+        let mut buf = vec![0u32; (len as usize).div_ceil(4)];
+        let res = GetTokenInformation(
+            token,
+            TokenIntegrityLevel,
+            Some(buf.as_mut_ptr() as *mut c_void),
+            len,
+            &mut len,
+        )
+        .map(|_| {
+            let sid = (*(buf.as_ptr() as *const TOKEN_MANDATORY_LABEL)).Label.Sid;
+            let count = *GetSidSubAuthorityCount(sid);
+
+            *GetSidSubAuthority(sid, count as u32 - 1)
+        });
+
+        let _ = CloseHandle(token);
+
+        res
+    }
+}
+
+/// Removes every privilege from the front end's token, e.g. `SeDebugPrivilege` which would
+/// let it open the target regardless of its security descriptor.
+fn drop_privileges() -> windows::core::Result<()> {
+    unsafe {
+        let mut token = HANDLE::default();
+        OpenProcessToken(
+            GetCurrentProcess(),
+            TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY,
+            &mut token,
+        )?;
+
+        let mut len = 0;
+        // only fails with ERROR_INSUFFICIENT_BUFFER after filling in `len`
+        let _ = GetTokenInformation(token, TokenPrivileges, None, 0, &mut len);
+
+        // u32 keeps the buffer aligned for TOKEN_PRIVILEGES
+        let mut buf = vec![0u32; (len as usize).div_ceil(4)];
+        let res = GetTokenInformation(
+            token,
+            TokenPrivileges,
+            Some(buf.as_mut_ptr() as *mut c_void),
+            len,
+            &mut len,
+        )
+        .and_then(|_| {
+            let privileges = buf.as_mut_ptr() as *mut TOKEN_PRIVILEGES;
+            let entries = std::slice::from_raw_parts_mut(
+                (*privileges).Privileges.as_mut_ptr(),
+                (*privileges).PrivilegeCount as usize,
+            );
+            for e in entries {
+                e.Attributes = SE_PRIVILEGE_REMOVED;
+            }
+
+            AdjustTokenPrivileges(token, false, Some(privileges), 0, None, None)
+        });
+
+        let _ = CloseHandle(token);
+
+        res
+    }
+}
@@ -0,0 +1,527 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use axum::{
+    Router,
+    extract::{Extension, Path, State},
+    http::{StatusCode, Uri},
+    middleware,
+    response::Json,
+    routing::{get, post},
+    serve,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::{runtime::Builder, signal, sync::oneshot};
+use tokio_rustls::rustls::ServerConfig;
+
+use crate::{
+    auth::{self, Scope, Tokens},
+    config::Identifier,
+    invoke::{Call, Invoker, Routes},
+    listener::{Bind, Listener},
+    requests::{MultiPayload, MultiPayloadRejection},
+    rpc,
+    tls::TlsListener,
+    ws,
+};
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Info {
+    pub base_name: String,
+    pub exec_path: String,
+    pub pid: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    /// `--wait`: no matching process yet
+    #[default]
+    Waiting,
+    Attached,
+    /// the target exited, waiting for it again when recovering
+    Detached,
+}
+
+/// Lifecycle events kept in `Status`, older ones are dropped.
+const EVENTS: usize = 16;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// the target exited, the session is detached
+    Exited,
+    /// waiting for the target to run again
+    Waiting,
+    /// injecting the target again failed, it's retried later
+    Failed,
+    Recovered,
+}
+
+/// Change in the lifecycle of the target, as logged to stderr.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Event {
+    /// counts events from 1, tells the ones already seen apart
+    pub seq: u64,
+    pub kind: EventKind,
+    pub message: String,
+}
+
+/// What `/info` reports, updated by the invocation thread.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Status {
+    pub state: SessionState,
+    /// `None` until attached, last injected process once detached
+    #[serde(flatten)]
+    pub info: Option<Info>,
+    /// times the target was injected again after exiting
+    #[serde(default)]
+    pub restarts: u32,
+    /// of the last target that exited, when it could be read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<u32>,
+    /// the payloads are being injected again, calls wait for the new procedures
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reloading: bool,
+    /// last lifecycle events, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<Event>,
+}
+
+impl Status {
+    pub fn attached(info: Info) -> Self {
+        Self {
+            state: SessionState::Attached,
+            info: Some(info),
+            restarts: 0,
+            exit_code: None,
+            reloading: false,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn attach(&mut self, info: Info) {
+        self.state = SessionState::Attached;
+        self.info = Some(info);
+        self.exit_code = None;
+        self.reloading = false;
+    }
+
+    pub fn push_event(&mut self, kind: EventKind, message: String) {
+        let seq = self.events.last().map_or(1, |e| e.seq + 1);
+        if self.events.len() == EVENTS {
+            self.events.remove(0);
+        }
+        self.events.push(Event { seq, kind, message });
+    }
+
+    /// Why calls can't be served right now, if they can't.
+    pub fn unavailable(&self) -> Option<&'static str> {
+        match self.state {
+            SessionState::Attached => None,
+            SessionState::Waiting => Some("target is not running yet"),
+            SessionState::Detached => Some("target exited, session is detached"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    pub invoker: Invoker,
+    /// of the first target, which answers unnamespaced routes
+    pub status: Arc<RwLock<Status>>,
+    /// every target by id, in configuration order
+    pub targets: Arc<Vec<(String, Arc<RwLock<Status>>)>>,
+    /// replaced when the configuration file is reloaded
+    pub paths: Arc<RwLock<Vec<Identifier>>>,
+    pub routes: Arc<RwLock<Routes>>,
+    pub document: Arc<RwLock<Value>>,
+}
+
+impl AppState {
+    /// Path name `name` is called as, token scopes are checked against it.
+    pub fn canonical(&self, name: &str) -> String {
+        self.routes.read().unwrap().canonical(name).to_string()
+    }
+}
+
+pub fn router(state: AppState, tokens: Arc<Tokens>) -> Router {
+    let app = Router::new()
+        .route("/info", get(info))
+        .route("/openapi.json", get(openapi))
+        .route("/procedures", get(procedures))
+        .route("/execute/{proc}", post(execute))
+        .route("/execute/{payload}/{proc}", post(payload_execute))
+        .route("/batch", post(batch))
+        .route("/ws", get(ws::handler))
+        .route("/rpc", post(rpc::handler))
+        .route("/targets", get(targets))
+        .route("/targets/{id}/info", get(target_info))
+        .route("/targets/{id}/execute/{proc}", post(target_execute))
+        .route(
+            "/targets/{id}/execute/{payload}/{proc}",
+            post(target_payload_execute),
+        )
+        .route("/broadcast/{proc}", post(broadcast))
+        .route("/broadcast/{payload}/{proc}", post(payload_broadcast));
+
+    #[cfg(feature = "grpc")]
+    let app = app.route_service(
+        "/epiphyte.Epiphyte/{*method}",
+        crate::grpc::service(state.clone()),
+    );
+
+    app.fallback(fallback)
+        .layer(middleware::from_fn_with_state(tokens, auth::authorize))
+        .with_state(state)
+}
+
+/// Serves `app` until ctrl+c or `stop`, blocking the calling thread on its own runtime.
+pub fn run(app: Router, bind: Bind, tls: Option<Arc<ServerConfig>>, stop: oneshot::Receiver<()>) {
+    let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+
+    runtime.block_on(async {
+        let listener = match Listener::bind(&bind).await {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("[ERROR] cannot listen on '{}': {}", bind, e);
+                return;
+            }
+        };
+
+        match tls {
+            Some(config) => serve(TlsListener::new(listener, config), app)
+                .with_graceful_shutdown(shutdown_signal(stop))
+                .await
+                .unwrap(),
+            None => serve(listener, app)
+                .with_graceful_shutdown(shutdown_signal(stop))
+                .await
+                .unwrap(),
+        }
+    });
+}
+
+async fn info(State(state): State<AppState>) -> Json<Status> {
+    Json(state.status.read().unwrap().clone())
+}
+
+async fn openapi(State(state): State<AppState>) -> Json<Value> {
+    Json(state.document.read().unwrap().clone())
+}
+
+async fn procedures(State(state): State<AppState>) -> Json<Vec<Identifier>> {
+    Json(state.paths.read().unwrap().clone())
+}
+
+async fn execute(
+    State(state): State<AppState>,
+    Path(proc): Path<String>,
+    Extension(scope): Extension<Scope>,
+    payload: MultiPayload,
+) -> (StatusCode, Json<Value>) {
+    let proc = state.canonical(&proc);
+    if !scope.allows(&proc) {
+        return auth::forbidden(&proc);
+    }
+    if let Some(res) = unavailable(&state.status) {
+        return res;
+    }
+
+    let start = Instant::now();
+
+    match state.invoker.call(proc, payload).await {
+        Ok(v) => (
+            StatusCode::OK,
+            Json(json!({
+                "message": v,
+                "elapsed_ms": start.elapsed().as_millis(),
+            })),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e })),
+        ),
+    }
+}
+
+/// `{payload}/{proc}` paths of `[[payloads]]`, the route splits them on the slash.
+async fn payload_execute(
+    state: State<AppState>,
+    Path((payload_name, proc)): Path<(String, String)>,
+    scope: Extension<Scope>,
+    payload: MultiPayload,
+) -> (StatusCode, Json<Value>) {
+    execute(
+        state,
+        Path(format!("{}/{}", payload_name, proc)),
+        scope,
+        payload,
+    )
+    .await
+}
+
+#[derive(Serialize)]
+struct TargetStatus {
+    id: String,
+    #[serde(flatten)]
+    status: Status,
+}
+
+async fn targets(State(state): State<AppState>) -> Json<Vec<TargetStatus>> {
+    let res = state
+        .targets
+        .iter()
+        .map(|(id, status)| TargetStatus {
+            id: id.clone(),
+            status: status.read().unwrap().clone(),
+        })
+        .collect();
+
+    Json(res)
+}
+
+fn target_status(state: &AppState, id: &str) -> Option<Arc<RwLock<Status>>> {
+    state
+        .targets
+        .iter()
+        .find(|(v, _)| v == id)
+        .map(|(_, status)| status.clone())
+}
+
+fn no_target(id: &str) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("no target '{}'", id) })),
+    )
+}
+
+async fn target_info(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Status>, (StatusCode, Json<Value>)> {
+    let status = target_status(&state, &id).ok_or_else(|| no_target(&id))?;
+
+    Ok(Json(status.read().unwrap().clone()))
+}
+
+async fn target_execute(
+    State(state): State<AppState>,
+    Path((id, proc)): Path<(String, String)>,
+    Extension(scope): Extension<Scope>,
+    payload: MultiPayload,
+) -> (StatusCode, Json<Value>) {
+    let proc = state.canonical(&proc);
+    if !scope.allows(&proc) {
+        return auth::forbidden(&proc);
+    }
+    let Some(status) = target_status(&state, &id) else {
+        return no_target(&id);
+    };
+    if let Some(res) = unavailable(&status) {
+        return res;
+    }
+
+    let start = Instant::now();
+
+    match state.invoker.call_target(id, proc, payload).await {
+        Ok(v) => (
+            StatusCode::OK,
+            Json(json!({
+                "message": v,
+                "elapsed_ms": start.elapsed().as_millis(),
+            })),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e })),
+        ),
+    }
+}
+
+async fn target_payload_execute(
+    state: State<AppState>,
+    Path((id, payload_name, proc)): Path<(String, String, String)>,
+    scope: Extension<Scope>,
+    payload: MultiPayload,
+) -> (StatusCode, Json<Value>) {
+    let proc = format!("{}/{}", payload_name, proc);
+    target_execute(state, Path((id, proc)), scope, payload).await
+}
+
+/// Calls every target in turn, targets that aren't attached fail on their own.
+async fn broadcast(
+    State(state): State<AppState>,
+    Path(proc): Path<String>,
+    Extension(scope): Extension<Scope>,
+    payload: MultiPayload,
+) -> (StatusCode, Json<Value>) {
+    let proc = state.canonical(&proc);
+    if !scope.allows(&proc) {
+        return auth::forbidden(&proc);
+    }
+
+    let start = Instant::now();
+
+    match state
+        .invoker
+        .broadcast(proc, payload, state.targets.len())
+        .await
+    {
+        Ok(replies) => {
+            let results: Vec<Value> = replies
+                .into_iter()
+                .map(|(id, result)| match result {
+                    Ok(v) => json!({ "target": id, "message": v }),
+                    Err(e) => json!({ "target": id, "error": e }),
+                })
+                .collect();
+
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "results": results,
+                    "elapsed_ms": start.elapsed().as_millis(),
+                })),
+            )
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e })),
+        ),
+    }
+}
+
+async fn payload_broadcast(
+    state: State<AppState>,
+    Path((payload_name, proc)): Path<(String, String)>,
+    scope: Extension<Scope>,
+    payload: MultiPayload,
+) -> (StatusCode, Json<Value>) {
+    broadcast(
+        state,
+        Path(format!("{}/{}", payload_name, proc)),
+        scope,
+        payload,
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct BatchItem {
+    proc: String,
+    body: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct BatchRequest {
+    items: Vec<BatchItem>,
+    #[serde(default)]
+    stop_on_error: bool,
+}
+
+async fn batch(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    Json(mut request): Json<BatchRequest>,
+) -> Result<(StatusCode, Json<Value>), MultiPayloadRejection> {
+    for item in &mut request.items {
+        item.proc = state.canonical(&item.proc);
+    }
+    if let Some(item) = request.items.iter().find(|i| !scope.allows(&i.proc)) {
+        return Ok(auth::forbidden(&item.proc));
+    }
+    if let Some(res) = unavailable(&state.status) {
+        return Ok(res);
+    }
+
+    let calls = request
+        .items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let payload = MultiPayload::from_value(item.body)
+                .map_err(|e| MultiPayloadRejection::from(format!("item {}: {}", i, e.message())))?;
+            Ok(Call {
+                path: item.proc,
+                payload,
+            })
+        })
+        .collect::<Result<Vec<_>, MultiPayloadRejection>>()?;
+
+    let start = Instant::now();
+
+    let res = match state.invoker.batch(calls, request.stop_on_error).await {
+        Ok(outcomes) => {
+            let results: Vec<Value> = outcomes
+                .into_iter()
+                .map(|o| match o.result {
+                    Ok(v) => json!({
+                        "proc": o.path,
+                        "message": v,
+                        "elapsed_ms": o.elapsed.as_millis(),
+                    }),
+                    Err(e) => json!({
+                        "proc": o.path,
+                        "error": e,
+                        "elapsed_ms": o.elapsed.as_millis(),
+                    }),
+                })
+                .collect();
+
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "results": results,
+                    "elapsed_ms": start.elapsed().as_millis(),
+                })),
+            )
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e })),
+        ),
+    };
+
+    Ok(res)
+}
+
+/// `503` while no target is attached, instead of failing every call.
+fn unavailable(status: &RwLock<Status>) -> Option<(StatusCode, Json<Value>)> {
+    let reason = status.read().unwrap().unavailable()?;
+
+    Some((
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({ "error": reason })),
+    ))
+}
+
+async fn fallback(uri: Uri) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({
+            "error": format!("'{uri}' not found")
+        })),
+    )
+}
+
+/// Resolves on ctrl+c, or once `stop` is fired or dropped by the invocation thread.
+async fn shutdown_signal(stop: oneshot::Receiver<()>) {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to set ctrl+c handler");
+    };
+
+    let terminate = async {
+        let _ = stop.await;
+    };
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
@@ -0,0 +1,560 @@
+use std::{
+    collections::HashMap, error::Error, mem, os::windows::io::AsRawHandle, path::PathBuf, thread,
+};
+
+use dll_syringe::{
+    Syringe,
+    process::{ModuleHandle, OwnedProcess, OwnedProcessModule, Process},
+};
+use windows::Win32::{
+    Foundation::HANDLE,
+    System::Threading::{GetExitCodeProcess, INFINITE, WaitForSingleObject},
+};
+
+use crate::{
+    config::{OnExisting, Options, Payload},
+    invoke::{Invoker, Procedures, Request, Routes},
+    launch::{self, Launch},
+    payload::{self, Metadata},
+    requests::MultiPayload,
+    server::Info,
+    shadow,
+};
+
+/// Payloads injected into the target process, with their procedures resolved.
+pub struct Session {
+    syringe: Syringe,
+    /// in injection order, empty once ejected until the payloads are injected again
+    modules: Vec<Module>,
+    payloads: Vec<Payload>,
+    routes: Routes,
+    on_existing: OnExisting,
+    pub info: Info,
+    pub procedures: Procedures,
+}
+
+/// Payload injected into the target.
+struct Module {
+    handle: OwnedProcessModule,
+    /// loaded from, a shadow copy unless an existing module was reused
+    path: PathBuf,
+}
+
+/// Symbols of every payload, in the same order.
+fn analyze(payloads: &[Payload]) -> Result<Vec<HashMap<String, Metadata>>, Box<dyn Error>> {
+    payloads
+        .iter()
+        .map(|p| {
+            payload::analyze_payload(&p.path, p.paths.clone())
+                .map_err(|e| format!("cannot analyze payload '{}': {}", p.name, e).into())
+        })
+        .collect()
+}
+
+/// Runs the init procedures of a launched target, then lets it run.
+fn start(launch: &Launch, procedures: &Procedures, pid: u32) -> Result<(), Box<dyn Error>> {
+    for path in &launch.init {
+        procedures
+            .call(path, MultiPayload::Signal)
+            .map_err(|e| format!("init procedure '{}' failed: {}", path, e))?;
+    }
+
+    if launch.is_suspended() {
+        launch::resume(pid)?;
+        eprintln!("[INFO] launched process resumed.");
+    }
+
+    Ok(())
+}
+
+/// Injects a shadow copy of the payload, the payload file itself stays free for rebuilds.
+fn inject_copy(syringe: &Syringe, payload: &Payload) -> Result<Module, Box<dyn Error>> {
+    let copy = shadow::copy(&payload.path)?;
+
+    let handle = syringe
+        .inject(&copy)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|m| Ok(m.try_to_owned()?));
+
+    match handle {
+        Ok(handle) => Ok(Module { handle, path: copy }),
+        Err(e) => {
+            shadow::remove(&copy);
+            Err(e)
+        }
+    }
+}
+
+/// Module of the payload already in the target, left behind by an earlier session.
+fn find_existing(
+    syringe: &Syringe,
+    payload: &Payload,
+    injected: &[ModuleHandle],
+) -> Result<Option<Module>, Box<dyn Error>> {
+    for module in syringe.process().modules()? {
+        // the payloads of this session are no leftover, whatever their names
+        if injected.contains(&module.handle()) {
+            continue;
+        }
+        // modules unloaded meanwhile have no path anymore
+        let Ok(path) = module.path() else {
+            continue;
+        };
+        if shadow::is_build_of(&payload.path, &path) {
+            let handle = module.try_to_owned()?;
+            return Ok(Some(Module { handle, path }));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Injects the payload, following `on_existing` when it's already loaded. The symbols of
+/// a reused module are analyzed from the file it was loaded from. `None` skips the lookup.
+fn load(
+    syringe: &Syringe,
+    payload: &Payload,
+    metadata: HashMap<String, Metadata>,
+    on_existing: Option<OnExisting>,
+    injected: &[ModuleHandle],
+) -> Result<(Module, HashMap<String, Metadata>), Box<dyn Error>> {
+    let existing = match on_existing {
+        Some(v) => find_existing(syringe, payload, injected)?.map(|m| (m, v)),
+        None => None,
+    };
+    let Some((existing, on_existing)) = existing else {
+        return Ok((inject_copy(syringe, payload)?, metadata));
+    };
+
+    match on_existing {
+        OnExisting::Error => Err(format!(
+            "already loaded from '{}', see on_existing",
+            existing.path.display()
+        ))?,
+        OnExisting::Replace => {
+            unload(syringe, &existing, payload)?;
+            eprintln!(
+                "[INFO] ejected payload '{}' already loaded from '{}'.",
+                payload.name,
+                existing.path.display()
+            );
+
+            Ok((inject_copy(syringe, payload)?, metadata))
+        }
+        OnExisting::Reuse => {
+            let metadata = payload::analyze_payload(&existing.path, payload.paths.clone())?;
+
+            // a copy of another build than the one on disk is still reused
+            if !is_on_disk(payload, &existing) {
+                eprintln!(
+                    "[WARNING] payload '{}' loaded from '{}' is not the build on disk.",
+                    payload.name,
+                    existing.path.display()
+                );
+            }
+            eprintln!(
+                "[INFO] reusing payload '{}' already loaded from '{}'.",
+                payload.name,
+                existing.path.display()
+            );
+
+            Ok((existing, metadata))
+        }
+    }
+}
+
+/// Whether `module` holds the build of `payload` on disk, loaded from the file itself or
+/// from its current shadow copy.
+fn is_on_disk(payload: &Payload, module: &Module) -> bool {
+    let on_disk = [
+        std::path::absolute(&payload.path).ok(),
+        shadow::current(&payload.path).ok(),
+    ];
+
+    on_disk
+        .iter()
+        .flatten()
+        .any(|v| v.as_os_str().eq_ignore_ascii_case(&module.path))
+}
+
+/// Modules injected by payload, `None` where the current one is kept, with the procedures
+/// of every payload.
+type Injected = (Vec<Option<Module>>, Procedures);
+
+/// Injects the payloads in order, ejecting the ones it injected when one fails. A module of
+/// `current` holding the build on disk is kept for its payload, the others are left loaded
+/// for the caller to eject.
+fn inject(
+    syringe: &Syringe,
+    payloads: &[Payload],
+    metadata: Vec<HashMap<String, Metadata>>,
+    pid: u32,
+    on_existing: Option<OnExisting>,
+    current: &[Module],
+) -> Result<Injected, Box<dyn Error>> {
+    let mut modules: Vec<Option<Module>> = Vec::with_capacity(payloads.len());
+    let mut procedures = Procedures::empty(pid);
+    let mut injected = current
+        .iter()
+        .map(|m| m.handle.handle())
+        .collect::<Vec<_>>();
+
+    for (i, (payload, metadata)) in payloads.iter().zip(metadata).enumerate() {
+        if let Some(module) = current.get(i).filter(|m| is_on_disk(payload, m)) {
+            procedures.extend(Procedures::resolve(
+                syringe,
+                module.handle.borrowed(),
+                metadata,
+                pid,
+            ));
+            modules.push(None);
+            continue;
+        }
+
+        let (module, metadata) = match load(syringe, payload, metadata, on_existing, &injected) {
+            Ok(v) => v,
+            Err(e) => {
+                for (module, payload) in modules.iter().zip(payloads).rev() {
+                    if let Some(module) = module
+                        && let Err(e) = unload(syringe, module, payload)
+                    {
+                        eprintln!("[ERROR] cannot eject payload: {}", e);
+                    }
+                }
+                Err(format!("cannot inject payload '{}': {}", payload.name, e))?
+            }
+        };
+
+        procedures.extend(Procedures::resolve(
+            syringe,
+            module.handle.borrowed(),
+            metadata,
+            pid,
+        ));
+        injected.push(module.handle.handle());
+        modules.push(Some(module));
+    }
+
+    Ok((modules, procedures))
+}
+
+/// Ejects a module of `payload`, after its teardown export if any, and removes its copy.
+fn unload(syringe: &Syringe, module: &Module, payload: &Payload) -> Result<(), Box<dyn Error>> {
+    if let Some(symbol) = &payload.teardown
+        && let Err(e) = teardown(syringe, &module.handle, symbol)
+    {
+        eprintln!(
+            "[WARNING] teardown of payload '{}' failed: {}",
+            payload.name, e
+        );
+    }
+
+    syringe.eject(module.handle.borrowed())?;
+    shadow::remove(&module.path);
+
+    Ok(())
+}
+
+/// Lets a payload release what it holds in the target before it is ejected.
+fn teardown(
+    syringe: &Syringe,
+    module: &OwnedProcessModule,
+    symbol: &str,
+) -> Result<(), Box<dyn Error>> {
+    let procedure =
+        unsafe { syringe.get_raw_procedure::<extern "system" fn()>(module.borrowed(), symbol)? }
+            .ok_or_else(|| format!("no export '{}'", symbol))?;
+
+    Ok(procedure.call()?)
+}
+
+impl Session {
+    /// Looks the target up, analyzes and injects the payloads. The target is launched when
+    /// it isn't running and `[launch]` is set, otherwise that's `Ok(None)`.
+    pub fn open(options: &Options) -> Result<Option<Self>, Box<dyn Error>> {
+        let metadata = analyze(options.payloads()?)?;
+
+        // with `[launch]` alone, a fresh target is started every time
+        let running = match &options.launch {
+            Some(_) if !options.target.is_set() => None,
+            _ => options.target()?.select()?,
+        };
+
+        let (target_process, launch) = match (running, &options.launch) {
+            (Some(p), _) => (p, None),
+            (None, Some(launch)) => (launch.spawn()?, Some(launch)),
+            (None, None) => {
+                eprintln!(
+                    "[ERROR] program with {} doesn't seem to be run...",
+                    options.target
+                );
+                return Ok(None);
+            }
+        };
+
+        // a launched target that missed its injection or initialization isn't worth keeping
+        // around, suspended or not
+        let launched = match launch.map(|_| target_process.try_clone()) {
+            Some(Ok(v)) => Some(v),
+            Some(Err(e)) => {
+                let _ = target_process.kill();
+                return Err(e.into());
+            }
+            None => None,
+        };
+        let res = Self::attach(options, target_process, metadata, launch);
+        if res.is_err()
+            && let Some(process) = launched
+            && let Err(e) = process.kill()
+        {
+            eprintln!("[ERROR] cannot kill launched target: {}", e);
+        }
+
+        res.map(Some)
+    }
+
+    /// Injects the payload once a matching process is running, `Ok(None)` until then. With
+    /// `[launch]`, the target is launched right away instead.
+    pub fn poll(options: &Options) -> Result<Option<Self>, Box<dyn Error>> {
+        if options.launch.is_some() {
+            return Self::open(options);
+        }
+
+        let Some(target_process) = options.target()?.select()? else {
+            return Ok(None);
+        };
+
+        let metadata = analyze(options.payloads()?)?;
+
+        Self::attach(options, target_process, metadata, None).map(Some)
+    }
+
+    fn attach(
+        options: &Options,
+        target_process: OwnedProcess,
+        metadata: Vec<HashMap<String, Metadata>>,
+        launch: Option<&Launch>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let payloads = options.payloads()?;
+
+        let pid = target_process.pid()?;
+        let base_name = target_process
+            .base_name()?
+            .to_str()
+            .unwrap_or("UNKNOWN BASE NAME")
+            .to_string();
+        let exec_path = target_process
+            .path()?
+            .to_str()
+            .unwrap_or("UNKNOWN EXEC PATH")
+            .to_string();
+
+        eprintln!(
+            "[INFO] injected process base name: {}, path: {}, pid: {}.",
+            base_name, exec_path, pid
+        );
+
+        if options.is_verbose {
+            for metadata in &metadata {
+                eprintln!();
+                if let Err(e) = payload::print_symbol_table(metadata) {
+                    eprintln!("[ERROR] failed to print symbols table: {}", e);
+                }
+            }
+            eprintln!();
+        }
+
+        let syringe = Syringe::for_process(target_process);
+        // a process launched suspended has no loader to enumerate modules with yet, and
+        // can't hold the payload anyway
+        let on_existing = launch.is_none().then_some(options.on_existing);
+        let (modules, procedures) =
+            inject(&syringe, payloads, metadata, pid.into(), on_existing, &[])?;
+        let modules = modules.into_iter().flatten().collect();
+        let routes = Routes::new(options);
+        let procedures = procedures.with_routes(routes.clone());
+
+        if let Some(launch) = launch {
+            start(launch, &procedures, pid.into())?;
+        }
+
+        Ok(Self {
+            syringe,
+            modules,
+            payloads: payloads.to_vec(),
+            routes,
+            on_existing: options.on_existing,
+            info: Info {
+                base_name,
+                exec_path,
+                pid: pid.into(),
+            },
+            procedures,
+        })
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.syringe.process().is_alive()
+    }
+
+    /// Tells the invocation loop as soon as the target process exits, from a thread blocked
+    /// on its handle.
+    pub fn watch(&self, invoker: Invoker) -> Result<(), Box<dyn Error>> {
+        let process = self.syringe.process().try_to_owned()?;
+        let pid = self.info.pid;
+
+        thread::spawn(move || {
+            let handle = HANDLE(process.as_raw_handle());
+            let mut code = 0;
+            let code = unsafe {
+                WaitForSingleObject(handle, INFINITE);
+                GetExitCodeProcess(handle, &mut code).ok().map(|_| code)
+            };
+
+            invoker.exited(pid, code);
+        });
+
+        Ok(())
+    }
+
+    pub fn handle(&mut self, request: Request) {
+        // a dropped receiver means the front end gave up waiting, nothing left to tell it
+        match request {
+            Request::Call(call, reply) => {
+                let _ = reply.send(self.procedures.call(&call.path, call.payload));
+            }
+            Request::Batch {
+                calls,
+                stop_on_error,
+                reply,
+            } => {
+                let _ = reply.send(self.procedures.batch(calls, stop_on_error));
+            }
+            Request::Eject(reply) => {
+                let _ = reply.send(
+                    self.eject()
+                        .map(|_| "payload ejected".into())
+                        .map_err(|e| e.to_string()),
+                );
+            }
+            // routed by `Targets` and the invocation loop
+            Request::Reload(_)
+            | Request::Target { .. }
+            | Request::Broadcast { .. }
+            | Request::Exited { .. } => {}
+        }
+    }
+
+    /// Injects the builds on disk next to the injected ones and resolves their procedures,
+    /// then ejects the builds they replace. The injected builds are kept as they were when
+    /// one fails, and so are payloads whose build didn't change.
+    pub fn reload(&mut self) -> Result<(), Box<dyn Error>> {
+        let metadata = analyze(&self.payloads)?;
+
+        let (modules, procedures) = inject(
+            &self.syringe,
+            &self.payloads,
+            metadata,
+            self.info.pid,
+            Some(self.on_existing),
+            &self.modules,
+        )?;
+        self.procedures = procedures.with_routes(self.routes.clone());
+
+        let mut current = mem::take(&mut self.modules).into_iter();
+        let mut replaced = Vec::new();
+        for (module, payload) in modules.into_iter().zip(&self.payloads) {
+            let old = current.next();
+            match module {
+                Some(module) => {
+                    replaced.extend(old.map(|m| (m, payload)));
+                    self.modules.push(module);
+                    eprintln!("[INFO] payload '{}' reloaded.", payload.path.display());
+                }
+                // kept, so there's one whenever it's `None`
+                None => self.modules.extend(old),
+            }
+        }
+
+        // calls already go to the new builds, an old one left loaded only leaks
+        for (module, payload) in replaced.iter().rev() {
+            if let Err(e) = unload(&self.syringe, module, payload) {
+                eprintln!(
+                    "[ERROR] cannot eject previous build of payload '{}': {}",
+                    payload.name, e
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the procedures of other paths of the same payloads against the modules
+    /// already injected, for [`Session::rebind`].
+    pub fn resolve(&self, payloads: &[Payload]) -> Result<Procedures, Box<dyn Error>> {
+        let mut procedures = Procedures::empty(self.info.pid);
+
+        // the injected copies are analyzed, the payload files may hold a newer build
+        for (module, payload) in self.modules.iter().zip(payloads) {
+            let metadata = payload::analyze_payload(&module.path, payload.paths.clone())
+                .map_err(|e| format!("cannot analyze payload '{}': {}", payload.name, e))?;
+            procedures.extend(Procedures::resolve(
+                &self.syringe,
+                module.handle.borrowed(),
+                metadata,
+                self.info.pid,
+            ));
+        }
+
+        Ok(procedures)
+    }
+
+    /// Swaps in procedures resolved for the new paths of the same payloads, without
+    /// ejecting them.
+    pub fn rebind(&mut self, payloads: Vec<Payload>, routes: Routes, procedures: Procedures) {
+        self.procedures = procedures.with_routes(routes.clone());
+        self.payloads = payloads;
+        self.routes = routes;
+    }
+
+    /// Ejects the payloads in reverse order, after their teardown export if any. Calls fail
+    /// until they are reloaded.
+    pub fn eject(&mut self) -> Result<(), Box<dyn Error>> {
+        while let Some(module) = self.modules.last() {
+            unload(
+                &self.syringe,
+                module,
+                &self.payloads[self.modules.len() - 1],
+            )?;
+            self.modules.pop();
+
+            let payload = &self.payloads[self.modules.len()];
+            eprintln!("[INFO] payload '{}' ejected.", payload.path.display());
+        }
+        self.procedures = Procedures::empty(self.info.pid);
+
+        Ok(())
+    }
+
+    pub fn close(mut self) -> Result<(), Box<dyn Error>> {
+        // nothing left to eject from a process that's gone
+        if !self.is_alive() {
+            self.abandon();
+            return Ok(());
+        }
+
+        self.eject()
+    }
+
+    /// Drops a session whose target exited without touching its memory.
+    pub fn abandon(self) {
+        // copies are released along with the process
+        for module in &self.modules {
+            shadow::remove(&module.path);
+        }
+
+        // freeing remote allocations fails once the address space is gone, and asserts in
+        // debug builds, only a handle is leaked instead
+        mem::forget(self);
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime},
+};
+
+use ring::digest;
+
+/// Where shadow copies are kept, shared by every session.
+fn dir() -> PathBuf {
+    env::temp_dir().join("epiphyte")
+}
+
+/// Hex digits of the payload identity kept in copy names.
+const ID_LEN: usize = 8;
+/// Hex digits of the content hash kept in copy names.
+const HASH_LEN: usize = 16;
+/// Copies untouched for this long aren't about to be injected by another session.
+const STALE_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Leading hex digits of the SHA-256 of `data`. Names outlive sessions, so the hash must
+/// not change with the toolchain.
+fn hash(data: &[u8], len: usize) -> String {
+    digest::digest(&digest::SHA256, data)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>()[..len]
+        .into()
+}
+
+fn is_hash(v: &str, len: usize) -> bool {
+    v.len() == len && v.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Copies of every build of `payload` are named `{stem}-{identity}-{hash}.{ext}`, the
+/// identity tells apart payloads whose files share a name. Lowercase, as module paths
+/// of the target may differ in case.
+fn prefix_and_suffix(payload: &Path) -> (String, String) {
+    let absolute = std::path::absolute(payload).unwrap_or_else(|_| payload.into());
+    let identity = hash(absolute.to_string_lossy().to_lowercase().as_bytes(), ID_LEN);
+
+    let stem = payload
+        .file_stem()
+        .map_or("payload".into(), |v| v.to_string_lossy());
+    let extension = payload
+        .extension()
+        .map_or(String::new(), |v| format!(".{}", v.to_string_lossy()));
+
+    (
+        format!("{}-{}-", stem, identity).to_lowercase(),
+        extension.to_lowercase(),
+    )
+}
+
+/// Shadow copy of a payload with this content, named after it.
+fn path(payload: &Path, content: &[u8]) -> PathBuf {
+    let (prefix, suffix) = prefix_and_suffix(payload);
+
+    dir().join(format!("{}{}{}", prefix, hash(content, HASH_LEN), suffix))
+}
+
+fn is_in_dir(path: &Path) -> bool {
+    // module paths of the target may differ in case from the temporary directory
+    path.parent()
+        .is_some_and(|p| p.as_os_str().eq_ignore_ascii_case(dir()))
+}
+
+/// Shadow copy of `payload` as it is on disk now.
+pub fn current(payload: &Path) -> io::Result<PathBuf> {
+    Ok(path(payload, &fs::read(payload)?))
+}
+
+/// Copies `payload` to be injected in its place, Windows keeps an injected file locked
+/// until it is ejected, which would block rebuilds of the payload.
+pub fn copy(payload: &Path) -> io::Result<PathBuf> {
+    // read once, a rebuild may replace the file meanwhile
+    let content = fs::read(payload)?;
+    let copy = path(payload, &content);
+
+    // the same build may already be injected elsewhere, and can't be written over then
+    if !touch(&copy) {
+        fs::create_dir_all(dir())?;
+
+        // written aside first, so a concurrent session never injects a partial copy
+        let partial = copy.with_extension(format!("{}.part", process::id()));
+        fs::write(&partial, &content)?;
+        if let Err(e) = fs::rename(&partial, &copy) {
+            let _ = fs::remove_file(&partial);
+            if !copy.exists() {
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(copy)
+}
+
+/// Marks an existing copy as in use, so [`collect`] leaves it alone until it's injected.
+/// `false` if there's no copy, or it's locked by a process holding it.
+fn touch(copy: &Path) -> bool {
+    fs::File::options()
+        .write(true)
+        .open(copy)
+        .and_then(|f| f.set_modified(SystemTime::now()))
+        .is_ok()
+}
+
+/// Whether `name` is the file name of a copy, `{stem}-{identity}-{hash}` with any
+/// extension.
+fn is_copy_name(name: &str) -> bool {
+    let stem = Path::new(name)
+        .file_stem()
+        .map_or(name.into(), |v| v.to_string_lossy());
+
+    stem.rsplit_once('-')
+        .and_then(|(rest, hash)| Some((rest.rsplit_once('-')?.1, hash)))
+        .is_some_and(|(identity, hash)| is_hash(identity, ID_LEN) && is_hash(hash, HASH_LEN))
+}
+
+/// Whether `module` is loaded from `payload` itself or from a copy of any of its builds.
+pub fn is_build_of(payload: &Path, module: &Path) -> bool {
+    if std::path::absolute(payload).is_ok_and(|v| v.as_os_str().eq_ignore_ascii_case(module)) {
+        return true;
+    }
+
+    let Some(name) = module.file_name() else {
+        return false;
+    };
+    let name = name.to_string_lossy().to_lowercase();
+    let (prefix, suffix) = prefix_and_suffix(payload);
+
+    is_in_dir(module)
+        && name
+            .strip_prefix(&prefix)
+            .and_then(|v| v.strip_suffix(&suffix))
+            .is_some_and(|v| is_hash(v, HASH_LEN))
+}
+
+/// Removes a copy once ejected, files outside of the copies directory are left alone.
+/// Another target may still hold the same build, its copy is left for that target to
+/// remove.
+pub fn remove(copy: &Path) {
+    if is_in_dir(copy) {
+        let _ = fs::remove_file(copy);
+    }
+}
+
+/// Removes copies left behind by earlier sessions that didn't eject, the ones still
+/// loaded in a process can't be removed and are kept. Recent copies and partial ones may
+/// belong to another session about to inject them.
+pub fn collect() {
+    let Ok(entries) = fs::read_dir(dir()) else {
+        return;
+    };
+
+    let is_stale = |entry: &fs::DirEntry| {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let age = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|v| v.elapsed().ok());
+
+        !name.ends_with(".part") && is_copy_name(&name) && age.is_some_and(|v| v >= STALE_AGE)
+    };
+
+    let removed = entries
+        .flatten()
+        .filter(|e| is_stale(e) && fs::remove_file(e.path()).is_ok())
+        .count();
+    if removed > 0 {
+        eprintln!("[INFO] removed {} stale payload copies.", removed);
+    }
+}
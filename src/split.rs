@@ -0,0 +1,419 @@
+//! Split mode: a broker process holds the injected payload and runs the REST server in a
+//! child front end process, which never gets a handle to the target and is confined by
+//! [`sandbox`](crate::sandbox) so it can't open one either.
+//!
+//! They talk over the child's stdin and stdout, one JSON object per line. The broker first
+//! sends `{"kind": "hello", "status": {...}}`, then the front end sends `call` or `batch` ops,
+//! each answered by a reply of the same kind and `id`, one at a time. In between, the broker
+//! sends `{"kind": "status", "status": {...}}` whenever the status of the target changes.
+
+use std::{
+    env,
+    error::Error,
+    io::{self, BufRead, StdoutLock, Write},
+    sync::{
+        Arc, RwLock,
+        mpsc::{self, TryRecvError},
+    },
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    runtime::Builder,
+    signal, task,
+    time::{MissedTickBehavior, interval},
+};
+
+use crate::{
+    config::DEFAULT_TARGET,
+    invoke::{Call, Invoker, Outcome, Pause, Reply as CallReply, Request},
+    requests::{MultiPayload, Text},
+    sandbox,
+    server::Status,
+};
+
+/// Front end to broker.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Op {
+    Call {
+        id: u64,
+        call: WireCall,
+    },
+    Batch {
+        id: u64,
+        calls: Vec<WireCall>,
+        stop_on_error: bool,
+    },
+}
+
+/// Broker to front end.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Reply {
+    Hello {
+        status: Status,
+    },
+    /// pushed whenever the status changed, not an answer to any op
+    Status {
+        status: Status,
+    },
+    Call {
+        id: u64,
+        result: CallReply,
+    },
+    Batch {
+        id: u64,
+        result: Result<Vec<WireOutcome>, String>,
+    },
+}
+
+impl Reply {
+    fn id(&self) -> Option<u64> {
+        match self {
+            Reply::Hello { .. } | Reply::Status { .. } => None,
+            Reply::Call { id, .. } | Reply::Batch { id, .. } => Some(*id),
+        }
+    }
+}
+
+/// `message` is set for `text` paths only.
+#[derive(Serialize, Deserialize)]
+struct WireCall {
+    proc: String,
+    message: Option<String>,
+}
+
+impl From<Call> for WireCall {
+    fn from(call: Call) -> Self {
+        let message = match call.payload {
+            MultiPayload::Text(text) => Some(text.message),
+            MultiPayload::Signal => None,
+        };
+
+        Self {
+            proc: call.path,
+            message,
+        }
+    }
+}
+
+impl From<WireCall> for Call {
+    fn from(call: WireCall) -> Self {
+        let payload = match call.message {
+            Some(message) => MultiPayload::Text(Text { message }),
+            None => MultiPayload::Signal,
+        };
+
+        Self {
+            path: call.proc,
+            payload,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireOutcome {
+    proc: String,
+    result: CallReply,
+    elapsed: Duration,
+}
+
+impl From<Outcome> for WireOutcome {
+    fn from(o: Outcome) -> Self {
+        Self {
+            proc: o.path,
+            result: o.result,
+            elapsed: o.elapsed,
+        }
+    }
+}
+
+impl From<WireOutcome> for Outcome {
+    fn from(o: WireOutcome) -> Self {
+        Self {
+            path: o.proc,
+            result: o.result,
+            elapsed: o.elapsed,
+        }
+    }
+}
+
+/// Broker side: starts this executable again as the front end and answers its ops until
+/// it exits, checking `status` for changes to push once per `period`. Blocks the calling
+/// thread on its own runtime.
+pub fn run_broker(invoker: Invoker, status: Arc<RwLock<Status>>, period: Duration) {
+    let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+
+    runtime.block_on(async {
+        if let Err(e) = broker(invoker, status, period).await {
+            eprintln!("[ERROR] front end process failed: {}", e);
+        }
+    });
+}
+
+async fn broker(
+    invoker: Invoker,
+    status: Arc<RwLock<Status>>,
+    period: Duration,
+) -> Result<(), Box<dyn Error>> {
+    // killed once this is dropped, or the broker exits any other way
+    let mut child = sandbox::spawn(
+        &env::current_exe()?,
+        env::args_os().skip(1).chain(["--front-end".into()]),
+    )
+    .map_err(|e| format!("cannot start a confined front end: {}", e))?;
+
+    let mut to_front = File::from_std(child.stdin.take().ok_or("front end stdin is not piped")?);
+    let mut lines = BufReader::new(File::from_std(
+        child.stdout.take().ok_or("front end stdout is not piped")?,
+    ))
+    .lines();
+
+    eprintln!("[INFO] front end started, pid: {}.", child.pid);
+
+    // compared serialized, it's what the front end gets anyway
+    let mut sent = serde_json::to_string(&*status.read().unwrap())?;
+    let hello = format!("{{\"kind\":\"hello\",\"status\":{}}}\n", sent);
+    to_front.write_all(hello.as_bytes()).await?;
+    to_front.flush().await?;
+
+    let mut ticker = interval(period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    break;
+                };
+
+                let op = serde_json::from_str::<Op>(&line)
+                    .map_err(|e| format!("malformed message from front end: {}", e))?;
+
+                let mut reply = serde_json::to_string(&answer(&invoker, op).await)?;
+                reply.push('\n');
+                to_front.write_all(reply.as_bytes()).await?;
+                to_front.flush().await?;
+            }
+            _ = ticker.tick() => {
+                let current = serde_json::to_string(&*status.read().unwrap())?;
+                if current != sent {
+                    let update = format!("{{\"kind\":\"status\",\"status\":{}}}\n", current);
+                    to_front.write_all(update.as_bytes()).await?;
+                    to_front.flush().await?;
+                    sent = current;
+                }
+            }
+            // the front end shares the console and shuts down on its own
+            _ = signal::ctrl_c() => {}
+        }
+    }
+
+    let code = task::spawn_blocking(move || child.wait()).await??;
+    eprintln!("[INFO] front end exited with code {}.", code);
+
+    Ok(())
+}
+
+async fn answer(invoker: &Invoker, op: Op) -> Reply {
+    match op {
+        Op::Call { id, call } => {
+            let call = Call::from(call);
+            Reply::Call {
+                id,
+                result: invoker.call(call.path, call.payload).await,
+            }
+        }
+        Op::Batch {
+            id,
+            calls,
+            stop_on_error,
+        } => {
+            let calls = calls.into_iter().map(Call::from).collect();
+            let result = invoker
+                .batch(calls, stop_on_error)
+                .await
+                .map(|v| v.into_iter().map(WireOutcome::from).collect());
+
+            Reply::Batch { id, result }
+        }
+    }
+}
+
+/// Front end side of the pipe, stands in for the session on the invocation thread.
+pub struct Broker {
+    writer: StdoutLock<'static>,
+    /// answers to ops, from the thread reading the pipe
+    replies: mpsc::Receiver<Result<Reply, String>>,
+    /// follows the updates pushed by the broker
+    status: Arc<RwLock<Status>>,
+    next_id: u64,
+}
+
+impl Broker {
+    /// Waits for the broker to introduce the injected process. Calls of `invoker` wait for
+    /// the broker while it's reloading.
+    pub fn connect(invoker: Invoker) -> Result<Self, Box<dyn Error>> {
+        let (replies_tx, replies) = mpsc::channel();
+        let status = Arc::new(RwLock::new(Status::default()));
+        let shared = status.clone();
+        // status updates come in whether or not a call is waiting for its reply
+        thread::spawn(move || listen(replies_tx, shared, invoker));
+
+        let broker = Self {
+            writer: io::stdout().lock(),
+            replies,
+            status,
+            next_id: 0,
+        };
+
+        match broker.receive()? {
+            Reply::Hello { .. } => Ok(broker),
+            _ => Err("broker didn't start with hello".into()),
+        }
+    }
+
+    pub fn status(&self) -> Arc<RwLock<Status>> {
+        self.status.clone()
+    }
+
+    /// Errors once the pipe is closed, so the front end stops when the broker is gone.
+    pub fn tick(&self) -> Result<(), Box<dyn Error>> {
+        match self.replies.try_recv() {
+            Err(TryRecvError::Empty) => Ok(()),
+            Err(TryRecvError::Disconnected) => Err("broker closed the pipe".into()),
+            Ok(Err(e)) => Err(e.into()),
+            Ok(Ok(_)) => Err("broker replied to no op".into()),
+        }
+    }
+
+    /// Forwards a request and waits for its reply. Errors mean the pipe is unusable.
+    pub fn handle(&mut self, request: Request) -> Result<(), Box<dyn Error>> {
+        // a dropped receiver means the front end gave up waiting, nothing left to tell it
+        match request {
+            Request::Call(call, reply) => {
+                let _ = reply.send(self.call(call)?);
+            }
+            Request::Target { id, call, reply } => {
+                let result = if id == DEFAULT_TARGET {
+                    self.call(call)?
+                } else {
+                    Err(format!("no target '{}'", id))
+                };
+                let _ = reply.send(result);
+            }
+            Request::Broadcast { call, reply } => {
+                let result = self.call(call)?;
+                let _ = reply.send(vec![(DEFAULT_TARGET.into(), result)]);
+            }
+            Request::Batch {
+                calls,
+                stop_on_error,
+                reply,
+            } => {
+                let result = match self.exchange(|id| Op::Batch {
+                    id,
+                    calls: calls.into_iter().map(WireCall::from).collect(),
+                    stop_on_error,
+                })? {
+                    Reply::Batch { result, .. } => result,
+                    _ => Err("broker replied to a batch with another kind")?,
+                };
+                match result {
+                    Ok(v) => {
+                        let _ = reply.send(v.into_iter().map(Outcome::from).collect());
+                    }
+                    // dropping the sender fails the batch on the server side
+                    Err(e) => eprintln!("[ERROR] broker failed a batch: {}", e),
+                }
+            }
+            Request::Reload(reply) | Request::Eject(reply) => {
+                let _ = reply.send(Err("the payload is held by the broker".into()));
+            }
+            // the broker watches the target
+            Request::Exited { .. } => {}
+        }
+
+        Ok(())
+    }
+
+    /// Forwards `call` to the broker, `--split` rules out `[[targets]]` so it goes to the
+    /// only target.
+    fn call(&mut self, call: Call) -> Result<CallReply, Box<dyn Error>> {
+        match self.exchange(|id| Op::Call {
+            id,
+            call: call.into(),
+        })? {
+            Reply::Call { result, .. } => Ok(result),
+            _ => Err("broker replied to a call with another kind")?,
+        }
+    }
+
+    fn exchange(&mut self, op: impl FnOnce(u64) -> Op) -> Result<Reply, Box<dyn Error>> {
+        self.next_id += 1;
+        let id = self.next_id;
+
+        writeln!(self.writer, "{}", serde_json::to_string(&op(id))?)?;
+        self.writer.flush()?;
+
+        let reply = self.receive()?;
+        if reply.id() != Some(id) {
+            Err(format!("broker replied out of order, expected id {}", id))?;
+        }
+
+        Ok(reply)
+    }
+
+    fn receive(&self) -> Result<Reply, Box<dyn Error>> {
+        match self.replies.recv() {
+            Ok(res) => Ok(res?),
+            Err(_) => Err("broker closed the pipe".into()),
+        }
+    }
+}
+
+/// Reads the pipe until it's closed, applying status updates and passing the rest on. The
+/// first error is passed on last.
+fn listen(
+    replies: mpsc::Sender<Result<Reply, String>>,
+    status: Arc<RwLock<Status>>,
+    invoker: Invoker,
+) {
+    let mut reader = io::stdin().lock();
+    let mut pause: Option<Pause> = None;
+
+    loop {
+        let mut line = String::new();
+        let reply = match reader.read_line(&mut line) {
+            Ok(0) => Err("broker closed the pipe".into()),
+            Ok(_) => serde_json::from_str::<Reply>(&line).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        let is_err = reply.is_err();
+        match reply {
+            Ok(Reply::Status { status: v }) => {
+                if v.reloading != pause.is_some() {
+                    pause = v.reloading.then(|| invoker.pause());
+                }
+                *status.write().unwrap() = v;
+            }
+            reply => {
+                if let Ok(Reply::Hello { status: v }) = &reply {
+                    *status.write().unwrap() = v.clone();
+                }
+                if replies.send(reply).is_err() {
+                    return;
+                }
+            }
+        }
+        if is_err {
+            return;
+        }
+    }
+}
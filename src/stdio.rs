@@ -0,0 +1,69 @@
+use std::{
+    io::{self, BufRead, Write},
+    time::Instant,
+};
+
+use serde_json::{Value, json};
+
+use crate::{invoke::Invoker, requests::MultiPayload};
+
+/// Reads one JSON request per line from stdin and writes one JSON response per line to
+/// stdout, until EOF.
+pub fn run(invoker: Invoker) {
+    let mut stdout = io::stdout().lock();
+
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("[ERROR] cannot read stdin: {}", e);
+                break;
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let reply = respond(&invoker, &line);
+        if writeln!(stdout, "{}", reply)
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// `{"proc": "greet", "message": "x"}` is answered like `/execute/greet` with body
+/// `{"message": "x"}`. Optional `id` is echoed back.
+fn respond(invoker: &Invoker, line: &str) -> Value {
+    let mut request = match serde_json::from_str::<Value>(line) {
+        Ok(Value::Object(v)) => v,
+        Ok(_) => return json!({ "error": "request is not an object" }),
+        Err(e) => return json!({ "error": format!("invalid request: {}", e) }),
+    };
+
+    let id = request.remove("id").unwrap_or(Value::Null);
+    let Some(Value::String(proc)) = request.remove("proc") else {
+        return json!({ "id": id, "error": "proc must be a string" });
+    };
+
+    let body = (!request.is_empty()).then_some(Value::Object(request));
+    let payload = match MultiPayload::from_value(body) {
+        Ok(v) => v,
+        Err(e) => return json!({ "id": id, "proc": proc, "error": e.message() }),
+    };
+
+    let start = Instant::now();
+
+    match invoker.call_blocking(proc.clone(), payload) {
+        Ok(v) => json!({
+            "id": id,
+            "proc": proc,
+            "message": v,
+            "elapsed_ms": start.elapsed().as_millis(),
+        }),
+        Err(e) => json!({ "id": id, "proc": proc, "error": e }),
+    }
+}
@@ -0,0 +1,218 @@
+use std::{error::Error, fmt, path::PathBuf};
+
+use clap::{Args, ValueEnum};
+use dll_syringe::process::OwnedProcess;
+use regex::Regex;
+use serde::Deserialize;
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
+
+/// Which process to inject, every criterion that is set must match.
+#[derive(Args, Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Selector {
+    /// substring of the process name, from `target_name`
+    #[arg(skip)]
+    #[serde(skip)]
+    pub name_contains: Option<String>,
+
+    /// process id, overrides config
+    #[arg(long)]
+    pub pid: Option<u32>,
+
+    /// exact process name (case insensitive), overrides config
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// regex on the process name, overrides config
+    #[arg(long)]
+    pub name_regex: Option<String>,
+
+    /// substring of the full command line, overrides config
+    #[arg(long)]
+    pub cmdline: Option<String>,
+
+    /// regex on the full command line, overrides config
+    #[arg(long)]
+    pub cmdline_regex: Option<String>,
+
+    /// parent process id, overrides config
+    #[arg(long)]
+    pub parent_pid: Option<u32>,
+
+    /// process to pick when several match (default: first), overrides config
+    #[arg(long, value_enum)]
+    pub pick: Option<Pick>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Pick {
+    /// lowest pid
+    #[default]
+    First,
+    /// latest start time
+    Newest,
+    /// earliest start time
+    Oldest,
+    /// fail unless exactly one process matches
+    Error,
+}
+
+/// Running process as seen by selectors.
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub pid: u32,
+    pub name: String,
+    pub exe: Option<PathBuf>,
+    pub cmdline: String,
+    pub parent_pid: Option<u32>,
+    /// seconds since epoch
+    pub start_time: u64,
+}
+
+/// Running processes sorted by pid. Command lines and executable paths of processes that
+/// cannot be read are left empty.
+pub fn processes() -> Vec<Candidate> {
+    let mut system = System::new();
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::All,
+        true,
+        ProcessRefreshKind::nothing()
+            .with_cmd(UpdateKind::Always)
+            .with_exe(UpdateKind::Always),
+    );
+
+    let mut res: Vec<Candidate> = system
+        .processes()
+        .values()
+        .map(|p| Candidate {
+            pid: p.pid().as_u32(),
+            name: p.name().to_string_lossy().into(),
+            exe: p.exe().map(PathBuf::from),
+            cmdline: p
+                .cmd()
+                .iter()
+                .map(|s| s.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" "),
+            parent_pid: p.parent().map(|v| v.as_u32()),
+            start_time: p.start_time(),
+        })
+        .collect();
+    res.sort_by_key(|p| p.pid);
+
+    res
+}
+
+impl Selector {
+    pub fn is_set(&self) -> bool {
+        self.name_contains.is_some()
+            || self.pid.is_some()
+            || self.name.is_some()
+            || self.name_regex.is_some()
+            || self.cmdline.is_some()
+            || self.cmdline_regex.is_some()
+            || self.parent_pid.is_some()
+    }
+
+    /// Fields set in `self` win over `other`.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            name_contains: self.name_contains.or(other.name_contains),
+            pid: self.pid.or(other.pid),
+            name: self.name.or(other.name),
+            name_regex: self.name_regex.or(other.name_regex),
+            cmdline: self.cmdline.or(other.cmdline),
+            cmdline_regex: self.cmdline_regex.or(other.cmdline_regex),
+            parent_pid: self.parent_pid.or(other.parent_pid),
+            pick: self.pick.or(other.pick),
+        }
+    }
+
+    pub fn filter(&self, processes: Vec<Candidate>) -> Result<Vec<Candidate>, Box<dyn Error>> {
+        let name_regex = self.name_regex.as_deref().map(Regex::new).transpose()?;
+        let cmdline_regex = self.cmdline_regex.as_deref().map(Regex::new).transpose()?;
+
+        let res = processes
+            .into_iter()
+            .filter(|p| {
+                self.pid.is_none_or(|v| p.pid == v)
+                    && self.parent_pid.is_none_or(|v| p.parent_pid == Some(v))
+                    && self
+                        .name_contains
+                        .as_ref()
+                        .is_none_or(|v| p.name.contains(v.as_str()))
+                    && self
+                        .name
+                        .as_ref()
+                        .is_none_or(|v| p.name.eq_ignore_ascii_case(v))
+                    && name_regex.as_ref().is_none_or(|r| r.is_match(&p.name))
+                    && self
+                        .cmdline
+                        .as_ref()
+                        .is_none_or(|v| p.cmdline.contains(v.as_str()))
+                    && cmdline_regex
+                        .as_ref()
+                        .is_none_or(|r| r.is_match(&p.cmdline))
+            })
+            .collect();
+
+        Ok(res)
+    }
+
+    /// Opens the matching process chosen by `pick`, `Ok(None)` when nothing matches.
+    pub fn select(&self) -> Result<Option<OwnedProcess>, Box<dyn Error>> {
+        let mut matches = self.filter(processes())?;
+
+        let picked = match self.pick.unwrap_or_default() {
+            Pick::First => matches.into_iter().next(),
+            Pick::Newest => matches.into_iter().max_by_key(|p| (p.start_time, p.pid)),
+            Pick::Oldest => matches.into_iter().min_by_key(|p| (p.start_time, p.pid)),
+            Pick::Error if matches.len() > 1 => {
+                let pids: Vec<String> = matches.iter().map(|p| p.pid.to_string()).collect();
+                return Err(format!(
+                    "{} processes match {}, pids: {}",
+                    matches.len(),
+                    self,
+                    pids.join(", ")
+                )
+                .into());
+            }
+            Pick::Error => matches.pop(),
+        };
+
+        match picked {
+            Some(p) => Ok(Some(OwnedProcess::from_pid(p.pid)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut criteria = Vec::new();
+        if let Some(v) = &self.name_contains {
+            criteria.push(format!("name containing '{}'", v));
+        }
+        if let Some(v) = self.pid {
+            criteria.push(format!("pid {}", v));
+        }
+        if let Some(v) = &self.name {
+            criteria.push(format!("name '{}'", v));
+        }
+        if let Some(v) = &self.name_regex {
+            criteria.push(format!("name matching '{}'", v));
+        }
+        if let Some(v) = &self.cmdline {
+            criteria.push(format!("command line containing '{}'", v));
+        }
+        if let Some(v) = &self.cmdline_regex {
+            criteria.push(format!("command line matching '{}'", v));
+        }
+        if let Some(v) = self.parent_pid {
+            criteria.push(format!("parent pid {}", v));
+        }
+
+        write!(f, "{}", criteria.join(", "))
+    }
+}
@@ -0,0 +1,479 @@
+use std::{
+    error::Error,
+    mem,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::oneshot;
+
+use crate::{
+    config::{OnExit, Options},
+    invoke::{Invoker, Outcome, Request, Routes},
+    server::{EventKind, SessionState, Status},
+    session::Session,
+    shadow,
+    watch::Watch,
+};
+
+/// A recovered target exiting sooner than this counts as a failed recovery.
+const MIN_UPTIME: Duration = Duration::from_secs(10);
+/// Longest delay between recovery attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Logs a lifecycle event and keeps it in `status`, where the front ends pick it up.
+fn event(status: &mut Status, kind: EventKind, message: String) {
+    let level = match kind {
+        EventKind::Exited => "WARNING",
+        EventKind::Failed => "ERROR",
+        EventKind::Waiting | EventKind::Recovered => "INFO",
+    };
+    eprintln!("[{}] {}", level, message);
+    status.push_event(kind, message);
+}
+
+/// Delay before the next recovery attempt, doubling with every failure in a row.
+fn backoff(interval: Duration, failures: u32) -> Duration {
+    if failures == 0 {
+        return Duration::ZERO;
+    }
+
+    interval
+        .saturating_mul(1 << failures.min(16))
+        .min(MAX_BACKOFF)
+}
+
+/// Sessions of every configured target, each going through its own lifecycle. The first
+/// one answers requests that don't name a target.
+pub struct Targets {
+    slots: Vec<Slot>,
+}
+
+struct Slot {
+    id: String,
+    options: Options,
+    status: Arc<RwLock<Status>>,
+    attachment: Attachment,
+    /// `--watch`: rebuilds of the payloads
+    watch: Option<Watch>,
+    /// pauses reply deadlines while reloading
+    invoker: Invoker,
+    attached_at: Instant,
+    /// failed recoveries in a row, to back off from a target that can't be recovered
+    failures: u32,
+}
+
+enum Attachment {
+    /// `--wait` or recovery: no matching process yet
+    Waiting {
+        deadline: Option<Instant>,
+        next_poll: Instant,
+    },
+    /// the target exited and `on_exit` isn't `recover`
+    Detached,
+    Session(Box<Session>),
+}
+
+impl Targets {
+    /// Injects every target, or starts waiting for them with `--wait`. `Ok(None)` when a
+    /// target isn't running, after ejecting the ones already injected.
+    pub fn open(options: &Options, watcher: &Invoker) -> Result<Option<Self>, Box<dyn Error>> {
+        shadow::collect();
+
+        let mut targets = Self { slots: Vec::new() };
+
+        for (id, options) in options.targets()? {
+            match Slot::open(id, options, watcher) {
+                Ok(Some(slot)) => targets.slots.push(slot),
+                res => {
+                    if let Err(e) = targets.close() {
+                        eprintln!("[ERROR] cannot eject payload: {}", e);
+                    }
+                    return res.map(|_| None);
+                }
+            }
+        }
+
+        Ok(Some(targets))
+    }
+
+    pub fn statuses(&self) -> Vec<(String, Arc<RwLock<Status>>)> {
+        self.slots
+            .iter()
+            .map(|s| (s.id.clone(), s.status.clone()))
+            .collect()
+    }
+
+    pub fn handle(&mut self, request: Request) {
+        // a dropped receiver means the front end gave up waiting, nothing left to tell it
+        match request {
+            Request::Target { id, call, reply } => {
+                match self.slots.iter_mut().find(|s| s.id == id) {
+                    Some(slot) => slot.handle(Request::Call(call, reply)),
+                    None => {
+                        let _ = reply.send(Err(format!("no target '{}'", id)));
+                    }
+                }
+            }
+            Request::Broadcast { call, reply } => {
+                let replies = self
+                    .slots
+                    .iter_mut()
+                    .map(|slot| {
+                        let (reply_tx, mut reply_rx) = oneshot::channel();
+                        slot.handle(Request::Call(call.clone(), reply_tx));
+                        let res = reply_rx
+                            .try_recv()
+                            .unwrap_or_else(|_| Err("target dropped the call".into()));
+
+                        (slot.id.clone(), res)
+                    })
+                    .collect();
+                let _ = reply.send(replies);
+            }
+            request => self.slots[0].handle(request),
+        }
+    }
+
+    /// Stops dispatching to a target that exited, then follows `on_exit`. `true` to shut
+    /// down, once every target is gone.
+    pub fn exited(&mut self, pid: u32, code: Option<u32>) -> bool {
+        // a late watcher of an earlier target is ignored
+        if let Some(slot) = self
+            .slots
+            .iter_mut()
+            .find(|s| matches!(&s.attachment, Attachment::Session(v) if v.info.pid == pid))
+        {
+            slot.exited(code);
+        }
+
+        self.slots.iter().all(|s| {
+            matches!(s.options.on_exit, OnExit::Shutdown)
+                && matches!(s.attachment, Attachment::Detached)
+        })
+    }
+
+    /// Looks for waiting targets, errors once a deadline has passed or the payload cannot
+    /// be injected.
+    pub fn tick(&mut self, watcher: &Invoker) -> Result<(), Box<dyn Error>> {
+        for slot in &mut self.slots {
+            slot.tick(watcher)?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies new paths of the same payloads to every target without ejecting them. Nothing
+    /// is applied if any target can't take them.
+    pub fn reconfigure(&mut self, options: &Options) -> Result<(), Box<dyn Error>> {
+        let targets = options.targets()?;
+
+        if targets.len() != self.slots.len()
+            || self
+                .slots
+                .iter()
+                .zip(&targets)
+                .any(|(s, (id, _))| &s.id != id)
+        {
+            Err("targets changed, restart to apply")?;
+        }
+
+        let mut resolved = Vec::with_capacity(targets.len());
+        for (slot, (_, options)) in self.slots.iter().zip(&targets) {
+            let same = slot.options.payloads.len() == options.payloads.len()
+                && slot
+                    .options
+                    .payloads
+                    .iter()
+                    .zip(&options.payloads)
+                    .all(|(a, b)| a.name == b.name && a.path == b.path);
+            if !same {
+                Err("payloads changed, restart to inject them")?;
+            }
+
+            resolved.push(match &slot.attachment {
+                Attachment::Session(session) => Some(session.resolve(&options.payloads)?),
+                _ => None,
+            });
+        }
+
+        for ((slot, (_, options)), procedures) in self.slots.iter_mut().zip(targets).zip(resolved) {
+            if let (Attachment::Session(session), Some(procedures)) =
+                (&mut slot.attachment, procedures)
+            {
+                session.rebind(options.payloads.clone(), Routes::new(&options), procedures);
+            }
+
+            // sessions injected later on take the new paths from here
+            slot.options.payloads = options.payloads;
+            slot.options.paths = options.paths;
+            slot.options.aliases = options.aliases;
+        }
+
+        Ok(())
+    }
+
+    /// Ejects every payload in reverse order, reporting the first failure.
+    pub fn close(self) -> Result<(), Box<dyn Error>> {
+        let mut res = Ok(());
+        for slot in self.slots.into_iter().rev() {
+            if let Attachment::Session(session) = slot.attachment {
+                eprintln!("[INFO] all good, ejecting payload...");
+                let closed = session.close();
+                if res.is_ok() {
+                    res = closed;
+                }
+            }
+        }
+
+        res
+    }
+}
+
+impl Slot {
+    fn open(
+        id: String,
+        options: Options,
+        watcher: &Invoker,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        let (attachment, status) = if let Some(deadline) = options.wait
+            && options.launch.is_none()
+        {
+            eprintln!(
+                "[INFO] waiting for a program with {} to run...",
+                options.target()?
+            );
+            let waiting = Attachment::Waiting {
+                deadline: deadline.map(|v| Instant::now() + v),
+                next_poll: Instant::now(),
+            };
+            (waiting, Status::default())
+        } else {
+            let Some(session) = Session::open(&options)? else {
+                return Ok(None);
+            };
+            session.watch(watcher.clone())?;
+            let status = Status::attached(session.info.clone());
+            (Attachment::Session(Box::new(session)), status)
+        };
+
+        Ok(Some(Self {
+            id,
+            watch: options
+                .is_watching
+                .then(|| Watch::new(options.payload_paths())),
+            invoker: watcher.clone(),
+            options,
+            status: Arc::new(RwLock::new(status)),
+            attachment,
+            attached_at: Instant::now(),
+            failures: 0,
+        }))
+    }
+
+    fn handle(&mut self, request: Request) {
+        match (&mut self.attachment, request) {
+            (Attachment::Waiting { .. }, request) => refuse(request, "target is not running yet"),
+            (Attachment::Detached, request) => {
+                refuse(request, "target exited, session is detached")
+            }
+            (Attachment::Session(_), Request::Reload(reply)) => {
+                // a dropped receiver means the front end gave up waiting
+                let _ = reply.send(
+                    self.reload()
+                        .map(|_| "payload reloaded".into())
+                        .map_err(|e| e.to_string()),
+                );
+            }
+            (Attachment::Session(session), request) => session.handle(request),
+        }
+    }
+
+    /// Injects the payloads again from disk. Requests sent meanwhile wait on the channel
+    /// until the new procedures are in place, their deadlines don't run out meanwhile.
+    fn reload(&mut self) -> Result<(), Box<dyn Error>> {
+        let Attachment::Session(session) = &mut self.attachment else {
+            Err("no payload injected")?
+        };
+
+        self.status.write().unwrap().reloading = true;
+        let pause = self.invoker.pause();
+        let res = session.reload();
+        drop(pause);
+        self.status.write().unwrap().reloading = false;
+
+        res
+    }
+
+    fn exited(&mut self, code: Option<u32>) {
+        // both events go out with the same update, front ends never see one without the other
+        let mut status = self.status.write().unwrap();
+        status.state = SessionState::Detached;
+        status.exit_code = code;
+        status.reloading = false;
+        let pid = status.info.as_ref().map(|v| v.pid).unwrap_or_default();
+        let message = match code {
+            Some(code) => format!(
+                "target process {} exited with code {}, session detached.",
+                pid, code
+            ),
+            None => format!("target process {} exited, session detached.", pid),
+        };
+        event(&mut status, EventKind::Exited, message);
+
+        let next = match self.options.on_exit {
+            OnExit::Shutdown | OnExit::Detach => Attachment::Detached,
+            OnExit::Recover => {
+                // a target crashing on start isn't launched again right away
+                if self.attached_at.elapsed() < MIN_UPTIME {
+                    self.failures += 1;
+                } else {
+                    self.failures = 0;
+                }
+                let delay = backoff(Duration::from_millis(self.options.timeout), self.failures);
+
+                let message = format!(
+                    "waiting for a program with {} to run again{}...",
+                    self.options.target,
+                    if delay.is_zero() {
+                        String::new()
+                    } else {
+                        format!(" in {} ms", delay.as_millis())
+                    }
+                );
+                event(&mut status, EventKind::Waiting, message);
+                Attachment::Waiting {
+                    deadline: None,
+                    next_poll: Instant::now() + delay,
+                }
+            }
+        };
+        drop(status);
+        if let Attachment::Session(session) = mem::replace(&mut self.attachment, next) {
+            session.abandon();
+        }
+    }
+
+    /// Polls at most once per loop timeout.
+    fn tick(&mut self, watcher: &Invoker) -> Result<(), Box<dyn Error>> {
+        let interval = Duration::from_millis(self.options.timeout);
+
+        if let Attachment::Session(_) = &self.attachment {
+            if self.watch.as_mut().is_some_and(|w| w.changed(interval)) {
+                // calls run on this thread, so none is in flight
+                eprintln!("[INFO] payload rebuilt, reloading...");
+                if let Err(e) = self.reload() {
+                    eprintln!(
+                        "[ERROR] cannot reload payload, keeping the injected one: {}",
+                        e
+                    );
+                }
+                if let Some(watch) = &mut self.watch {
+                    watch.rebase();
+                }
+            }
+            return Ok(());
+        }
+
+        let Attachment::Waiting {
+            deadline,
+            next_poll,
+        } = &mut self.attachment
+        else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        if now < *next_poll {
+            return Ok(());
+        }
+        *next_poll = now + interval;
+
+        // a recovered target may still be starting or briefly not openable, only `--wait`
+        // gives up on a failed injection
+        let recovering = self.status.read().unwrap().state == SessionState::Detached;
+        let session = match Session::poll(&self.options) {
+            Ok(v) => v,
+            Err(e) if recovering => {
+                self.failures += 1;
+                let delay = backoff(interval, self.failures);
+                *next_poll = now + delay;
+                let message = format!(
+                    "cannot inject recovered target, retrying in {} ms: {}",
+                    delay.as_millis(),
+                    e
+                );
+                event(
+                    &mut self.status.write().unwrap(),
+                    EventKind::Failed,
+                    message,
+                );
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+
+        match session {
+            Some(session) => {
+                session.watch(watcher.clone())?;
+
+                let mut status = self.status.write().unwrap();
+                if status.state == SessionState::Detached {
+                    status.restarts += 1;
+                    let message =
+                        format!("target recovered, {} restart(s) so far.", status.restarts);
+                    event(&mut status, EventKind::Recovered, message);
+                }
+                status.attach(session.info.clone());
+
+                // the build on disk was just injected
+                if let Some(watch) = &mut self.watch {
+                    *watch = Watch::new(self.options.payload_paths());
+                }
+
+                self.attachment = Attachment::Session(Box::new(session));
+                self.attached_at = now;
+            }
+            None if deadline.is_some_and(|v| now >= v) => {
+                Err(format!(
+                    "no program with {} started in time.",
+                    self.options.target
+                ))?;
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Fails requests that arrive while no payload is injected.
+fn refuse(request: Request, reason: &str) {
+    // a dropped receiver means the front end gave up waiting, nothing left to tell it
+    match request {
+        Request::Call(_, reply)
+        | Request::Reload(reply)
+        | Request::Eject(reply)
+        | Request::Target { reply, .. } => {
+            let _ = reply.send(Err(reason.into()));
+        }
+        Request::Batch {
+            calls,
+            stop_on_error,
+            reply,
+        } => {
+            let count = if stop_on_error { 1 } else { calls.len() };
+            let outcomes = calls
+                .into_iter()
+                .take(count)
+                .map(|c| Outcome {
+                    path: c.path,
+                    result: Err(reason.into()),
+                    elapsed: Duration::ZERO,
+                })
+                .collect();
+            let _ = reply.send(outcomes);
+        }
+        Request::Broadcast { .. } | Request::Exited { .. } => {}
+    }
+}
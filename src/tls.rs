@@ -0,0 +1,343 @@
+use std::{fs, io, path::PathBuf, sync::Arc, time::Duration};
+
+use serde::Deserialize;
+use tokio::{task::JoinSet, time::timeout};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ClientConfig, RootCertStore, ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+        server::WebPkiClientVerifier,
+    },
+    server::TlsStream,
+};
+
+use crate::listener::{Connection, Listener};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Tls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// CA bundle to verify client certificates against, enables mTLS
+    pub client_ca: Option<PathBuf>,
+}
+
+impl Tls {
+    pub fn server_config(&self) -> Result<Arc<ServerConfig>, Box<dyn std::error::Error>> {
+        let read = |path: &PathBuf| {
+            fs::read(path).map_err(|e| format!("cannot read '{}': {}", path.display(), e))
+        };
+
+        let cert = read(&self.cert)?;
+        let key = read(&self.key)?;
+        let client_ca = self.client_ca.as_ref().map(read).transpose()?;
+
+        server_config(&cert, &key, client_ca.as_deref())
+    }
+}
+
+/// Builds rustls server config from PEM encoded certificate chain, private key and
+/// optional client CA bundle.
+pub fn server_config(
+    cert: &[u8],
+    key: &[u8],
+    client_ca: Option<&[u8]>,
+) -> Result<Arc<ServerConfig>, Box<dyn std::error::Error>> {
+    let chain = CertificateDer::pem_slice_iter(cert).collect::<Result<Vec<_>, _>>()?;
+    if chain.is_empty() {
+        Err("no certificate found in TLS certificate file")?;
+    }
+    let key = PrivateKeyDer::from_pem_slice(key)?;
+
+    let builder = ServerConfig::builder();
+    let builder = match client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for c in CertificateDer::pem_slice_iter(ca) {
+                roots.add(c?)?;
+            }
+            builder.with_client_cert_verifier(WebPkiClientVerifier::builder(roots.into()).build()?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder.with_single_cert(chain, key)?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    if cfg!(feature = "grpc") {
+        // gRPC needs HTTP/2, which is only served with the grpc feature
+        config.alpn_protocols.insert(0, b"h2".to_vec());
+    }
+
+    Ok(Arc::new(config))
+}
+
+/// Builds rustls client config trusting only the PEM encoded certificates in `ca`, which
+/// can be the server's own self-signed certificate.
+pub fn client_config(ca: &[u8]) -> Result<Arc<ClientConfig>, Box<dyn std::error::Error>> {
+    let mut roots = RootCertStore::empty();
+    for c in CertificateDer::pem_slice_iter(ca) {
+        roots.add(c?)?;
+    }
+    if roots.is_empty() {
+        Err("no certificate found in CA file")?;
+    }
+
+    let mut config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Arc::new(config))
+}
+
+/// Wraps [`Listener`] and performs TLS handshakes in the background, so a slow client
+/// doesn't hold back accepting other connections.
+pub struct TlsListener {
+    inner: Listener,
+    acceptor: TlsAcceptor,
+    handshakes: JoinSet<Option<(TlsStream<Connection>, String)>>,
+}
+
+impl TlsListener {
+    pub fn new(inner: Listener, config: Arc<ServerConfig>) -> Self {
+        Self {
+            inner,
+            acceptor: TlsAcceptor::from(config),
+            handshakes: JoinSet::new(),
+        }
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<Connection>;
+    type Addr = String;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            tokio::select! {
+                (io, addr) = axum::serve::Listener::accept(&mut self.inner) => {
+                    let acceptor = self.acceptor.clone();
+                    self.handshakes.spawn(async move {
+                        match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(io)).await {
+                            Ok(Ok(stream)) => Some((stream, addr)),
+                            Ok(Err(e)) => {
+                                eprintln!("[ERROR] TLS handshake with {} failed: {}", addr, e);
+                                None
+                            }
+                            Err(_) => {
+                                eprintln!("[ERROR] TLS handshake with {} timed out", addr);
+                                None
+                            }
+                        }
+                    });
+                }
+                Some(Ok(Some(conn))) = self.handshakes.join_next(), if !self.handshakes.is_empty() => {
+                    return conn;
+                }
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        axum::serve::Listener::local_addr(&self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{
+        BasicConstraints, CertificateParams, CertifiedIssuer, DnType, ExtendedKeyUsagePurpose,
+        IsCa, KeyPair,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, duplex};
+    use tokio_rustls::{
+        TlsConnector,
+        rustls::pki_types::{PrivateKeyDer, ServerName},
+    };
+
+    use super::*;
+
+    struct Ca(CertifiedIssuer<'static, KeyPair>);
+
+    /// PEM encoded certificate and private key.
+    struct Leaf {
+        cert: String,
+        key: String,
+    }
+
+    impl Ca {
+        fn new(name: &str) -> Self {
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params.distinguished_name.push(DnType::CommonName, name);
+
+            Self(CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap())
+        }
+
+        fn pem(&self) -> String {
+            self.0.pem()
+        }
+
+        fn issue(&self, name: &str, usage: ExtendedKeyUsagePurpose) -> Leaf {
+            let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            params.distinguished_name.push(DnType::CommonName, name);
+            params.extended_key_usages = vec![usage];
+
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &self.0).unwrap();
+
+            Leaf {
+                cert: cert.pem(),
+                key: key.serialize_pem(),
+            }
+        }
+
+        fn server(&self) -> Leaf {
+            self.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth)
+        }
+
+        fn client(&self) -> Leaf {
+            self.issue("client", ExtendedKeyUsagePurpose::ClientAuth)
+        }
+    }
+
+    /// Same as [`client_config`], presenting `leaf` as the client certificate.
+    fn client_config_with_cert(ca: &str, leaf: &Leaf) -> Arc<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        for c in CertificateDer::pem_slice_iter(ca.as_bytes()) {
+            roots.add(c.unwrap()).unwrap();
+        }
+        let chain = CertificateDer::pem_slice_iter(leaf.cert.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let key = PrivateKeyDer::from_pem_slice(leaf.key.as_bytes()).unwrap();
+
+        let mut config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(chain, key)
+            .unwrap();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Arc::new(config)
+    }
+
+    /// Runs both sides of a handshake over an in-memory pipe, then a byte from the server to
+    /// the client. The server side fails when it rejects the client certificate.
+    async fn handshake(
+        server: Arc<ServerConfig>,
+        client: Arc<ClientConfig>,
+    ) -> (io::Result<()>, io::Result<()>) {
+        let (client_io, server_io) = duplex(16 * 1024);
+
+        let server = async {
+            let mut stream = TlsAcceptor::from(server).accept(server_io).await?;
+            assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+            stream.write_all(b"!").await?;
+            stream.shutdown().await
+        };
+        let client = async {
+            let name = ServerName::try_from("localhost").unwrap();
+            let mut stream = TlsConnector::from(client).connect(name, client_io).await?;
+            let mut buf = [0; 1];
+            stream.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"!");
+            Ok(())
+        };
+
+        tokio::join!(server, client)
+    }
+
+    #[tokio::test]
+    async fn handshake_without_client_auth() {
+        let ca = Ca::new("epiphyte test CA");
+        let server = ca.server();
+
+        let server = server_config(server.cert.as_bytes(), server.key.as_bytes(), None).unwrap();
+        let client = client_config(ca.pem().as_bytes()).unwrap();
+
+        let (server, client) = handshake(server, client).await;
+        server.unwrap();
+        client.unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_rejects_server_from_untrusted_ca() {
+        let server = Ca::new("epiphyte test CA").server();
+        let other = Ca::new("other CA");
+
+        let server = server_config(server.cert.as_bytes(), server.key.as_bytes(), None).unwrap();
+        let client = client_config(other.pem().as_bytes()).unwrap();
+
+        let (server, client) = handshake(server, client).await;
+        assert!(server.is_err());
+        assert!(client.is_err());
+    }
+
+    #[tokio::test]
+    async fn mtls_accepts_client_cert_from_client_ca() {
+        let ca = Ca::new("epiphyte test CA");
+        let server = ca.server();
+        let client_ca = Ca::new("epiphyte client CA");
+
+        let server = server_config(
+            server.cert.as_bytes(),
+            server.key.as_bytes(),
+            Some(client_ca.pem().as_bytes()),
+        )
+        .unwrap();
+        let client = client_config_with_cert(&ca.pem(), &client_ca.client());
+
+        let (server, client) = handshake(server, client).await;
+        server.unwrap();
+        client.unwrap();
+    }
+
+    #[tokio::test]
+    async fn mtls_rejects_client_cert_from_untrusted_ca() {
+        let ca = Ca::new("epiphyte test CA");
+        let server = ca.server();
+        let client_ca = Ca::new("epiphyte client CA");
+        let other = Ca::new("other CA");
+
+        let server = server_config(
+            server.cert.as_bytes(),
+            server.key.as_bytes(),
+            Some(client_ca.pem().as_bytes()),
+        )
+        .unwrap();
+        let client = client_config_with_cert(&ca.pem(), &other.client());
+
+        let (server, client) = handshake(server, client).await;
+        assert!(server.is_err());
+        assert!(client.is_err());
+    }
+
+    #[tokio::test]
+    async fn mtls_rejects_client_without_cert() {
+        let ca = Ca::new("epiphyte test CA");
+        let server = ca.server();
+        let client_ca = Ca::new("epiphyte client CA");
+
+        let server = server_config(
+            server.cert.as_bytes(),
+            server.key.as_bytes(),
+            Some(client_ca.pem().as_bytes()),
+        )
+        .unwrap();
+        let client = client_config(ca.pem().as_bytes()).unwrap();
+
+        let (server, client) = handshake(server, client).await;
+        assert!(server.is_err());
+        assert!(client.is_err());
+    }
+
+    #[test]
+    fn configs_need_certificates() {
+        let server = Ca::new("epiphyte test CA").server();
+
+        assert!(server_config(b"", server.key.as_bytes(), None).is_err());
+        assert!(client_config(b"").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Modification time and size of a file, `None` while it can't be read.
+type Stamp = Option<(SystemTime, u64)>;
+
+/// Notices files changed on disk, payload builds or the configuration file. A change is
+/// only reported once the files stopped changing between two polls, so a build or an
+/// editor still writing them isn't picked up half done.
+pub struct Watch {
+    paths: Vec<PathBuf>,
+    /// of the files in use
+    injected: Vec<Stamp>,
+    /// at the previous poll
+    seen: Vec<Stamp>,
+    next_poll: Instant,
+}
+
+fn stamps(paths: &[PathBuf]) -> Vec<Stamp> {
+    paths
+        .iter()
+        .map(|p| {
+            let metadata = fs::metadata(p).ok()?;
+            Some((metadata.modified().ok()?, metadata.len()))
+        })
+        .collect()
+}
+
+impl Watch {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let injected = stamps(&paths);
+
+        Self {
+            paths,
+            seen: injected.clone(),
+            injected,
+            next_poll: Instant::now(),
+        }
+    }
+
+    /// Polls at most once per `interval`, `true` once a rebuild has settled.
+    pub fn changed(&mut self, interval: Duration) -> bool {
+        let now = Instant::now();
+        if now < self.next_poll {
+            return false;
+        }
+        self.next_poll = now + interval;
+
+        let current = stamps(&self.paths);
+        let settled = current == self.seen && current.iter().all(Option::is_some);
+        let changed = settled && current != self.injected;
+        self.seen = current;
+
+        changed
+    }
+
+    /// Takes the files on disk as the ones in use, after a reload attempt.
+    pub fn rebase(&mut self) {
+        self.injected = self.seen.clone();
+    }
+}
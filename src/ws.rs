@@ -0,0 +1,88 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{
+        Extension, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::{auth::Scope, requests::MultiPayload, server::AppState};
+
+#[derive(Deserialize)]
+struct Frame {
+    id: Option<Value>,
+    proc: String,
+    args: Option<Value>,
+}
+
+pub async fn handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+) -> Response {
+    ws.on_upgrade(move |socket| session(socket, state, scope))
+}
+
+/// Answers every text frame with a `{id, result|error, elapsed_ms}` frame, in order.
+async fn session(mut socket: WebSocket, state: AppState, scope: Scope) {
+    while let Some(Ok(msg)) = socket.recv().await {
+        let text = match msg {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        let reply = match serde_json::from_str::<Frame>(&text) {
+            Ok(frame) => call(&state, &scope, frame).await,
+            Err(e) => json!({ "id": null, "error": format!("invalid frame: {}", e) }),
+        };
+
+        if socket
+            .send(Message::Text(reply.to_string().into()))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+async fn call(state: &AppState, scope: &Scope, frame: Frame) -> Value {
+    let id = frame.id.unwrap_or(Value::Null);
+    let proc = state.canonical(&frame.proc);
+
+    if !scope.allows(&proc) {
+        return json!({
+            "id": id,
+            "error": format!("token is not allowed to execute '{}'", proc),
+        });
+    }
+
+    if let Some(reason) = state.status.read().unwrap().unavailable() {
+        return json!({ "id": id, "error": reason });
+    }
+
+    let payload = match MultiPayload::from_value(frame.args) {
+        Ok(v) => v,
+        Err(e) => return json!({ "id": id, "error": e.message() }),
+    };
+
+    let start = Instant::now();
+
+    match state.invoker.call(proc, payload).await {
+        Ok(v) => json!({
+            "id": id,
+            "result": v,
+            "elapsed_ms": start.elapsed().as_millis(),
+        }),
+        Err(e) => json!({
+            "id": id,
+            "error": e,
+            "elapsed_ms": start.elapsed().as_millis(),
+        }),
+    }
+}